@@ -3,7 +3,11 @@ use std::{collections::HashMap, sync::RwLock};
 use crate::{
     config::Config,
     otel::metrics::Metrics,
-    probe::model::{ProbeResult, StoryResult},
+    probe::{
+        auth::TokenCache,
+        model::{ProbeResult, StoryResult},
+        snapshots::Snapshots,
+    },
 };
 
 // Limits the number of results we store per probe. Once we go over this amount we remove the earliest.
@@ -12,6 +16,8 @@ const PROBE_RESULT_LIMIT: usize = 100;
 pub struct AppState {
     pub probe_results: RwLock<HashMap<String, Vec<ProbeResult>>>,
     pub story_results: RwLock<HashMap<String, Vec<StoryResult>>>,
+    pub snapshots: Snapshots,
+    pub tokens: TokenCache,
     pub config: Config,
     pub metrics: Metrics,
 }
@@ -21,6 +27,8 @@ impl AppState {
         AppState {
             probe_results: RwLock::new(HashMap::new()),
             story_results: RwLock::new(HashMap::new()),
+            snapshots: Snapshots::new(),
+            tokens: TokenCache::new(),
             config,
             metrics: Metrics::new(),
         }
@@ -1,10 +1,17 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tracing::warn;
 
+use crate::errors::MissingEnvVarsError;
+use crate::probe::expectations::compile_expectations;
+use crate::probe::http_probe::validate_resolve;
 use crate::probe::model::Probe;
 use crate::probe::model::Story;
+use crate::probe::variables::compile_extract;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -12,6 +19,33 @@ pub struct Config {
     pub probes: Vec<Probe>,
     #[serde(default)]
     pub stories: Vec<Story>,
+    // Fails probes and story steps which contain variables that can't be resolved, rather
+    // than substituting an empty string. Can be overridden per story.
+    #[serde(default)]
+    pub strict_variables: bool,
+    // Static values usable in every probe and story as ${{vars.name}}
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub variables: HashMap<String, String>,
+}
+
+// Read from the config file before anything else, as they decide how it is loaded
+#[derive(Debug, Default, Deserialize)]
+struct EnvOptions {
+    #[serde(default)]
+    env_substitution: EnvSubstitution,
+    // Fails startup if any referenced environment variable isn't set
+    #[serde(default)]
+    require_env_vars: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EnvSubstitution {
+    // Environment variables are replaced in the config text before it is parsed
+    #[default]
+    Load,
+    // Environment variables are resolved each time a request is built
+    Runtime,
 }
 
 pub async fn load_config<P: Into<PathBuf>>(path: P) -> Result<Config, Box<dyn std::error::Error>> {
@@ -25,16 +59,55 @@ pub async fn load_config<P: Into<PathBuf>>(path: P) -> Result<Config, Box<dyn st
             panic!("Failed to read config file: {:?}, err {}", path, e)
         }
     };
-    let config = replace_env_vars(&config);
-    let config: Config = serde_yaml::from_str(&config)?;
+    // Some configs are only valid YAML once environment variables are replaced, so these
+    // fall back to the defaults, replacing them when loading as before
+    let env_options: EnvOptions = serde_yaml::from_str(&config).unwrap_or_default();
+    if env_options.require_env_vars {
+        let missing = missing_env_vars(&config);
+        if !missing.is_empty() {
+            return Err(Box::new(MissingEnvVarsError { names: missing }));
+        }
+    }
+    let config = match env_options.env_substitution {
+        EnvSubstitution::Load => replace_env_vars(&config),
+        EnvSubstitution::Runtime => config,
+    };
+    let mut config: Config = serde_yaml::from_str(&config)?;
+    compile_config_expectations(&mut config)?;
     Ok(config)
 }
 
+fn compile_config_expectations(config: &mut Config) -> Result<(), Box<dyn std::error::Error>> {
+    for probe in &mut config.probes {
+        let location = format!("probe '{}'", probe.name);
+        compile_expectations(&mut probe.expectations, &location)?;
+        validate_resolve(&probe.resolve, &location)?;
+    }
+    for story in &mut config.stories {
+        for step in &mut story.steps {
+            let location = format!("step '{}' in story '{}'", step.name, story.name);
+            compile_expectations(&mut step.expectations, &location)?;
+            compile_extract(&mut step.extract, &location)?;
+            validate_resolve(&step.resolve, &location)?;
+        }
+    }
+    Ok(())
+}
+
+lazy_static! {
+    // Only plain references are replaced when loading, so that expressions such as
+    // `${{ env.SECRET | base64 }}` are left to be resolved at runtime
+    static ref ENV_VAR_REGEX: Regex =
+        Regex::new(r"\$\{\{\s*env\.([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").unwrap();
+    static ref PLACEHOLDER_REGEX: Regex = Regex::new(r"\$\{\{(.*?)\}\}").unwrap();
+    // An env value, either as the whole expression or as a function argument
+    static ref ENV_REFERENCE_REGEX: Regex =
+        Regex::new(r"(?:^|[(,])\s*env\.([A-Za-z_][A-Za-z0-9_]*)").unwrap();
+}
+
 pub fn replace_env_vars(content: &str) -> String {
-    let re = regex::Regex::new(r"\$\{\{\s*env\.(.*?)\s*\}\}").unwrap();
-    let replaced = re.replace_all(content, |caps: &regex::Captures| {
+    let replaced = ENV_VAR_REGEX.replace_all(content, |caps: &regex::Captures| {
         let var_name = &caps[1];
-        // panics on missing enivronment variables, probably desirable?
         match std::env::var(var_name) {
             Ok(val) => val,
             Err(_) => {
@@ -49,6 +122,23 @@ pub fn replace_env_vars(content: &str) -> String {
     replaced.to_string()
 }
 
+// Names of environment variables referenced anywhere in the config which aren't set
+fn missing_env_vars(content: &str) -> Vec<String> {
+    let mut missing: Vec<String> = PLACEHOLDER_REGEX
+        .captures_iter(content)
+        .flat_map(|placeholder| {
+            ENV_REFERENCE_REGEX
+                .captures_iter(placeholder.get(1).unwrap().as_str())
+                .map(|caps| caps[1].to_owned())
+                .collect::<Vec<_>>()
+        })
+        .filter(|name| std::env::var(name).is_err())
+        .collect();
+    missing.sort();
+    missing.dedup();
+    missing
+}
+
 #[cfg(test)]
 mod config_tests {
     use crate::{config::load_config, PRODZILLA_YAML};
@@ -69,6 +159,78 @@ mod config_tests {
         assert_eq!(1, config.stories.len(), "Stories length should be 1");
     }
 
+    #[tokio::test]
+    async fn test_invalid_regex_rejected_on_load() {
+        let content = r#"
+probes:
+  - name: bad-regex-probe
+    url: https://your.site/some/path
+    http_method: GET
+    expectations:
+      - field: Body
+        operation: Matches
+        value: "[unclosed"
+    schedule:
+      initial_delay: 0
+      interval: 60
+"#;
+        let path = env::temp_dir().join("prodzilla_invalid_regex_test.yml");
+        tokio::fs::write(&path, content).await.unwrap();
+
+        let error = load_config(path).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Invalid expectation at expectations[0] for probe 'bad-regex-probe'"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_extract_regex_rejected_on_load() {
+        let content = r#"
+stories:
+  - name: bad-extract-story
+    steps:
+      - name: login
+        url: https://your.site/login
+        http_method: POST
+        extract:
+          order:
+            Regex: "order-(\\d+"
+    schedule:
+      initial_delay: 0
+      interval: 60
+"#;
+        let path = env::temp_dir().join("prodzilla_invalid_extract_test.yml");
+        tokio::fs::write(&path, content).await.unwrap();
+
+        let error = load_config(path).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Invalid extract 'order' for step 'login' in story 'bad-extract-story'"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_resolve_rejected_on_load() {
+        let content = r#"
+probes:
+  - name: green-backend
+    url: https://api.your.site/health
+    http_method: GET
+    resolve:
+      api.your.site:443: green
+    schedule:
+      initial_delay: 0
+      interval: 60
+"#;
+        let path = env::temp_dir().join("prodzilla_invalid_resolve_test.yml");
+        tokio::fs::write(&path, content).await.unwrap();
+
+        let error = load_config(path).await.unwrap_err();
+        assert_eq!(
+            "Invalid request: Invalid resolve entry 'api.your.site:443: green', expected IP addresses for probe 'green-backend'",
+            error.to_string()
+        );
+    }
+
     #[tokio::test]
     async fn test_env_substitution() {
         env::set_var("TEST_ENV_VAR", "test_value");
@@ -79,4 +241,78 @@ mod config_tests {
             replaced
         );
     }
+
+    #[tokio::test]
+    async fn test_env_substitution_leaves_expressions() {
+        env::set_var("TEST_ENV_EXPRESSION_VAR", "test_value");
+        let content =
+            "${{ env.TEST_ENV_EXPRESSION_VAR | base64 }} ${{env.TEST_ENV_EXPRESSION_VAR}}";
+        let replaced = super::replace_env_vars(content);
+        assert_eq!(
+            "${{ env.TEST_ENV_EXPRESSION_VAR | base64 }} test_value",
+            replaced
+        );
+    }
+
+    #[tokio::test]
+    async fn test_runtime_env_substitution_not_replaced_on_load() {
+        env::set_var("TEST_RUNTIME_ENV_VAR", "test_value");
+        let content = r#"
+env_substitution: runtime
+probes:
+  - name: runtime-env-probe
+    url: https://your.site/${{ env.TEST_RUNTIME_ENV_VAR }}
+    http_method: GET
+    schedule:
+      initial_delay: 0
+      interval: 60
+"#;
+        let path = env::temp_dir().join("prodzilla_runtime_env_test.yml");
+        tokio::fs::write(&path, content).await.unwrap();
+
+        let config = load_config(path).await.unwrap();
+        assert_eq!(
+            "https://your.site/${{ env.TEST_RUNTIME_ENV_VAR }}",
+            config.probes[0].url
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_env_vars_fail_load_when_required() {
+        let content = r#"
+require_env_vars: true
+probes:
+  - name: missing-env-probe
+    url: https://your.site/${{ env.TEST_MISSING_ENV_VAR_A }}
+    http_method: GET
+    with:
+      headers:
+        X-Signature: ${{ hmac_sha256(env.TEST_MISSING_ENV_VAR_B, request.body) }}
+        X-Token: ${{ steps.login.response.body.env.token }}
+        X-Other: ${{ vars.env.TEST_MISSING_ENV_VAR_C }}
+    schedule:
+      initial_delay: 0
+      interval: 60
+"#;
+        let path = env::temp_dir().join("prodzilla_missing_env_test.yml");
+        tokio::fs::write(&path, content).await.unwrap();
+
+        let error = load_config(path).await.unwrap_err();
+        assert_eq!(
+            "Environment variables are not set: TEST_MISSING_ENV_VAR_A, TEST_MISSING_ENV_VAR_B",
+            error.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_env_options_default_when_only_valid_after_substitution() {
+        env::set_var("TEST_ENV_EXTRA_CONFIG", "variables: {region: eu-west-1}");
+        let content = "probes: []\n${{ env.TEST_ENV_EXTRA_CONFIG }}\n";
+        assert!(serde_yaml::from_str::<super::EnvOptions>(content).is_err());
+        let path = env::temp_dir().join("prodzilla_env_extra_config_test.yml");
+        tokio::fs::write(&path, content).await.unwrap();
+
+        let config = load_config(path).await.unwrap();
+        assert_eq!("eu-west-1", config.variables["region"]);
+    }
 }
@@ -21,6 +21,7 @@ pub struct ExpectationFailedError {
     pub body: String,
     pub operation: ExpectOperation,
     pub status_code: u32,
+    pub reason: Option<String>,
 }
 
 impl Error for ExpectationFailedError {}
@@ -31,7 +32,11 @@ impl std::fmt::Display for ExpectationFailedError {
             f,
             "Failed to meet expectation for field '{:?}' with operation {:?} {:?}.",
             self.field, self.operation, self.expected,
-        )
+        )?;
+        if let Some(reason) = &self.reason {
+            write!(f, " {}", reason)?;
+        }
+        Ok(())
     }
 }
 
@@ -39,8 +44,230 @@ impl std::fmt::Debug for ExpectationFailedError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
-            "Failed to meet expectation for field '{:?}' with operation {:?} {:?}. Received: status '{}', body '{}'",
-            self.field, self.operation, self.expected, self.status_code, self.body
+            "Failed to meet expectation for field '{:?}' with operation {:?} {:?}.",
+            self.field, self.operation, self.expected,
+        )?;
+        if let Some(reason) = &self.reason {
+            write!(f, " {}", reason)?;
+        }
+        write!(
+            f,
+            " Received: status '{}', body '{}'",
+            self.status_code, self.body
+        )
+    }
+}
+
+pub enum ExpectationFailure {
+    Field(ExpectationFailedError),
+    // None of the any_of expectations were met. Holds the failures from each of them.
+    AnyOf(Vec<ExpectationFailure>),
+    // An expectation inside a not was met. Holds a description of that expectation.
+    Not(String),
+}
+
+impl std::fmt::Display for ExpectationFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExpectationFailure::Field(failure) => write!(f, "{}", failure),
+            ExpectationFailure::AnyOf(failures) => {
+                write!(f, "None of the any_of expectations were met:")?;
+                for failure in failures {
+                    write!(f, " ({})", failure)?;
+                }
+                Ok(())
+            }
+            ExpectationFailure::Not(expectation) => {
+                write!(
+                    f,
+                    "Expected not to meet expectation {}, but it was met.",
+                    expectation
+                )
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ExpectationFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExpectationFailure::Field(failure) => write!(f, "{:?}", failure),
+            ExpectationFailure::AnyOf(failures) => f.debug_list().entries(failures.iter()).finish(),
+            ExpectationFailure::Not(_) => write!(f, "{}", self),
+        }
+    }
+}
+
+pub struct ExpectationsFailedError {
+    pub failures: Vec<ExpectationFailure>,
+}
+
+impl Error for ExpectationsFailedError {}
+
+impl std::fmt::Display for ExpectationsFailedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.failures.as_slice() {
+            [failure] => write!(f, "{}", failure),
+            failures => {
+                write!(f, "{} expectations failed:", failures.len())?;
+                for (i, failure) in failures.iter().enumerate() {
+                    write!(f, " [{}] {}", i + 1, failure)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ExpectationsFailedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.failures.iter()).finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum NumericComparisonError {
+    // Holds the start of the received value, or None if it's sensitive
+    ReceivedNotNumeric(Option<String>),
+    ExpectedNotNumeric(String),
+    InvalidRange(String),
+}
+
+impl Error for NumericComparisonError {}
+
+impl std::fmt::Display for NumericComparisonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NumericComparisonError::ReceivedNotNumeric(Some(value)) => {
+                write!(f, "Received value '{}' is not a number.", value)
+            }
+            NumericComparisonError::ReceivedNotNumeric(None) => {
+                write!(f, "Received value is not a number.")
+            }
+            NumericComparisonError::ExpectedNotNumeric(value) => {
+                write!(f, "Expected value '{}' is not a number.", value)
+            }
+            NumericComparisonError::InvalidRange(value) => write!(
+                f,
+                "Expected value '{}' is not a range in the form 'lower|upper'.",
+                value
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidExpectationError {
+    pub location: String,
+    // Where the expectation is within any groups, e.g. `expectations[2].any_of[1]`
+    pub path: String,
+    pub reason: String,
+}
+
+impl Error for InvalidExpectationError {}
+
+impl std::fmt::Display for InvalidExpectationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid expectation at {} for {}: {}",
+            self.path, self.location, self.reason
         )
     }
 }
+
+#[derive(Debug)]
+pub struct InvalidExtractError {
+    pub location: String,
+    pub name: String,
+    pub reason: String,
+}
+
+impl Error for InvalidExtractError {}
+
+impl std::fmt::Display for InvalidExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid extract '{}' for {}: {}",
+            self.name, self.location, self.reason
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnresolvedVariable {
+    pub placeholder: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for UnresolvedVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Could not resolve variable '{}': {}",
+            self.placeholder, self.reason
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct UnresolvedVariablesError {
+    pub variables: Vec<UnresolvedVariable>,
+}
+
+impl Error for UnresolvedVariablesError {}
+
+impl std::fmt::Display for UnresolvedVariablesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.variables.as_slice() {
+            [variable] => write!(f, "{}", variable),
+            variables => {
+                write!(f, "{} variables could not be resolved:", variables.len())?;
+                for (i, variable) in variables.iter().enumerate() {
+                    write!(f, " [{}] {}", i + 1, variable)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MissingEnvVarsError {
+    pub names: Vec<String>,
+}
+
+impl Error for MissingEnvVarsError {}
+
+impl std::fmt::Display for MissingEnvVarsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Environment variables are not set: {}",
+            self.names.join(", ")
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidRequestError(pub String);
+
+impl Error for InvalidRequestError {}
+
+impl std::fmt::Display for InvalidRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invalid request: {}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct AuthError(pub String);
+
+impl Error for AuthError {}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Authentication failed: {}", self.0)
+    }
+}
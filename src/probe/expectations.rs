@@ -1,27 +1,54 @@
+use std::sync::Arc;
+
 use crate::errors::ExpectationFailedError;
+use crate::errors::ExpectationFailure;
+use crate::errors::ExpectationsFailedError;
+use crate::errors::InvalidExpectationError;
+use crate::errors::NumericComparisonError;
+use crate::probe::markup::css_selector_value;
+use crate::probe::markup::parse_xpath;
+use crate::probe::markup::xpath_value;
+use crate::probe::model::EndpointResult;
 use crate::probe::model::ExpectField;
 use crate::probe::model::ExpectOperation;
+use crate::probe::model::Expectation;
+use crate::probe::model::JsonSchemaSource;
 use crate::probe::model::ProbeExpectation;
+use crate::probe::snapshots::diff_shapes;
+use crate::probe::snapshots::json_shape;
+use crate::probe::snapshots::Snapshots;
+use jsonschema::error::ValidationErrorKind;
+use jsonschema::JSONSchema;
 use regex::Regex;
+use serde_json::Value;
+use serde_json_path::JsonPath;
 use tracing::debug;
 
+// Only the first few schema violations are reported, to keep alerts readable
+const MAX_REPORTED_SCHEMA_VIOLATIONS: usize = 5;
+// Received values which aren't numbers are cut short in failure reasons, as they can be
+// the whole body
+const MAX_RECEIVED_PREVIEW_CHARS: usize = 32;
+
 pub fn validate_response(
     step_name: &String,
-    status_code: u32,
-    body: String,
-    expectations: &Option<Vec<ProbeExpectation>>,
-) -> Result<(), ExpectationFailedError> {
+    response: &EndpointResult,
+    expectations: &Option<Vec<Expectation>>,
+    snapshots: &Snapshots,
+) -> Result<(), ExpectationsFailedError> {
     match expectations {
-        Some(expect_back) => match validate_response_internal(expect_back, status_code, body) {
-            Ok(_) => {
-                debug!("Successful response for {}, as expected", step_name);
-                Ok(())
-            }
-            Err(e) => {
-                debug!("Successful response for {}, not as expected!", step_name);
-                Err(e)
+        Some(expect_back) => {
+            match validate_response_internal(expect_back, response, snapshots, step_name) {
+                Ok(_) => {
+                    debug!("Successful response for {}, as expected", step_name);
+                    Ok(())
+                }
+                Err(e) => {
+                    debug!("Successful response for {}, not as expected!", step_name);
+                    Err(e)
+                }
             }
-        },
+        }
         None => {
             // TODO:
             // If we don't have any expectations, default to checking status is 200
@@ -35,52 +62,433 @@ pub fn validate_response(
     }
 }
 
+// State shared while checking one response against all of its expectations
+struct Validation<'a> {
+    response: &'a EndpointResult,
+    snapshots: &'a Snapshots,
+    snapshot_prefix: &'a str,
+    // Only recorded once every expectation has passed
+    new_snapshots: Vec<(String, Value)>,
+}
+
 pub fn validate_response_internal(
-    expect: &Vec<ProbeExpectation>,
-    status_code: u32,
-    body: String,
+    expect: &[Expectation],
+    response: &EndpointResult,
+    snapshots: &Snapshots,
+    snapshot_prefix: &str,
+) -> Result<(), ExpectationsFailedError> {
+    let mut validation = Validation {
+        response,
+        snapshots,
+        snapshot_prefix,
+        new_snapshots: vec![],
+    };
+    // Every expectation is checked, so that all problems are reported at once
+    let failures = validate_all_of(expect, "", &mut validation);
+
+    if failures.is_empty() {
+        for (key, shape) in validation.new_snapshots {
+            snapshots.record(key, shape);
+        }
+        Ok(())
+    } else {
+        Err(ExpectationsFailedError { failures })
+    }
+}
+
+// Returns the reasons the expectation wasn't met, which is empty if it was met.
+// `position` identifies the expectation within any groups, e.g. "1.0".
+fn validate_group(
+    expect: &Expectation,
+    position: &str,
+    validation: &mut Validation,
+) -> Vec<ExpectationFailure> {
+    match expect {
+        Expectation::AllOf { all_of } => validate_all_of(all_of, position, validation),
+        Expectation::AnyOf { any_of } => {
+            let mut failures = vec![];
+            for (index, expectation) in any_of.iter().enumerate() {
+                let expectation_failures =
+                    validate_group(expectation, &format!("{}.{}", position, index), validation);
+                if expectation_failures.is_empty() {
+                    return vec![];
+                }
+                failures.extend(expectation_failures);
+            }
+            vec![ExpectationFailure::AnyOf(failures)]
+        }
+        Expectation::Not { not } => {
+            if validate_group(not, &format!("{}.not", position), validation).is_empty() {
+                vec![ExpectationFailure::Not(not.to_string())]
+            } else {
+                vec![]
+            }
+        }
+        Expectation::Field(expectation) => {
+            let result = match expectation.operation {
+                ExpectOperation::MatchesSnapshot => {
+                    validate_snapshot(expectation, position, validation)
+                }
+                _ => validate_expectation(expectation, validation.response),
+            };
+            match result {
+                Ok(()) => vec![],
+                Err(failure) => vec![ExpectationFailure::Field(failure)],
+            }
+        }
+    }
+}
+
+fn validate_all_of(
+    expect: &[Expectation],
+    position: &str,
+    validation: &mut Validation,
+) -> Vec<ExpectationFailure> {
+    expect
+        .iter()
+        .enumerate()
+        .flat_map(|(index, expectation)| {
+            let position = match position {
+                "" => index.to_string(),
+                _ => format!("{}.{}", position, index),
+            };
+            validate_group(expectation, &position, validation)
+        })
+        .collect()
+}
+
+// The first response is recorded as the snapshot. Later responses fail if their
+// structure differs, ignoring the values themselves.
+fn validate_snapshot(
+    expect: &ProbeExpectation,
+    position: &str,
+    validation: &mut Validation,
 ) -> Result<(), ExpectationFailedError> {
-    for expectation in expect {
-        validate_expectation(expectation, status_code, &body)?;
+    let response = validation.response;
+    let shape = resolve_field(&expect.field, response)
+        .and_then(|received| {
+            serde_json::from_str::<Value>(&received)
+                .map_err(|e| format!("Value is not valid JSON: {}.", e))
+        })
+        .and_then(|received| json_shape(&received, &expect.ignore_paths))
+        .map_err(|reason| expectation_failed(expect, response, Some(reason)))?;
+
+    let key = format!("{}#{}", validation.snapshot_prefix, position);
+    match validation.snapshots.get(&key) {
+        Some(snapshot) => {
+            let differences = diff_shapes(&snapshot, &shape);
+            if differences.is_empty() {
+                Ok(())
+            } else {
+                let reason = format!(
+                    "Response structure differs from the snapshot: {}",
+                    differences.join("; ")
+                );
+                Err(expectation_failed(expect, response, Some(reason)))
+            }
+        }
+        None => {
+            validation.new_snapshots.push((key, shape));
+            Ok(())
+        }
     }
+}
 
+// Prepares expectations when the config is loaded. `location` names the probe or step
+// the expectations belong to, so that errors can point at the right part of the config.
+pub fn compile_expectations(
+    expectations: &mut Option<Vec<Expectation>>,
+    location: &str,
+) -> Result<(), InvalidExpectationError> {
+    for (index, expectation) in expectations.iter_mut().flatten().enumerate() {
+        compile_group(expectation, &format!("expectations[{}]", index), location)?;
+    }
     Ok(())
 }
 
-fn expectation_met(operation: &ExpectOperation, expected: &String, received: &String) -> bool {
-    match operation {
+fn compile_group(
+    expectation: &mut Expectation,
+    path: &str,
+    location: &str,
+) -> Result<(), InvalidExpectationError> {
+    let (name, group) = match expectation {
+        Expectation::AllOf { all_of } => ("all_of", all_of),
+        Expectation::AnyOf { any_of } => ("any_of", any_of),
+        Expectation::Not { not } => return compile_group(not, &format!("{}.not", path), location),
+        Expectation::Field(expectation) => {
+            return compile_expectation(expectation).map_err(|reason| InvalidExpectationError {
+                location: location.to_owned(),
+                path: path.to_owned(),
+                reason,
+            })
+        }
+    };
+    for (index, expectation) in group.iter_mut().enumerate() {
+        compile_group(
+            expectation,
+            &format!("{}.{}[{}]", path, name, index),
+            location,
+        )?;
+    }
+    Ok(())
+}
+
+fn compile_expectation(expectation: &mut ProbeExpectation) -> Result<(), String> {
+    if let ExpectField::XPath(expression) = &expectation.field {
+        parse_xpath(expression)?;
+    }
+    match (&expectation.operation, &expectation.schema) {
+        (ExpectOperation::Matches, _) => {
+            let regex = Regex::new(&expectation.value).map_err(|e| {
+                format!("Invalid regular expression '{}': {}", expectation.value, e)
+            })?;
+            expectation.compiled.regex = Some(regex);
+            Ok(())
+        }
+        (ExpectOperation::MatchesSchema, Some(source)) => {
+            expectation.compiled.schema = Some(Arc::new(compile_schema(source)?));
+            Ok(())
+        }
+        (ExpectOperation::MatchesSchema, None) => {
+            Err("MatchesSchema requires a 'schema' to be set.".to_owned())
+        }
+        (ExpectOperation::MatchesSnapshot, _) => {
+            for path in &expectation.ignore_paths {
+                JsonPath::parse(path)
+                    .map_err(|e| format!("Invalid ignored JSONPath '{}': {}", path, e))?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn compile_schema(source: &JsonSchemaSource) -> Result<JSONSchema, String> {
+    let schema = match source {
+        JsonSchemaSource::Inline(schema) => schema.clone(),
+        JsonSchemaSource::File(schema_file) => {
+            let content = std::fs::read_to_string(&schema_file.file).map_err(|e| {
+                format!(
+                    "Failed to read JSON Schema file '{}': {}",
+                    schema_file.file, e
+                )
+            })?;
+            serde_json::from_str(&content).map_err(|e| {
+                format!(
+                    "JSON Schema file '{}' is not valid JSON: {}",
+                    schema_file.file, e
+                )
+            })?
+        }
+    };
+    JSONSchema::compile(&schema).map_err(|e| format!("Invalid JSON Schema: {}", e))
+}
+
+fn expectation_met(
+    operation: &ExpectOperation,
+    expected: &String,
+    received: &String,
+) -> Result<bool, NumericComparisonError> {
+    let met = match operation {
         ExpectOperation::Equals => expected == received,
         ExpectOperation::NotEquals => expected != received,
         ExpectOperation::Contains => received.contains(expected),
         ExpectOperation::NotContains => !received.contains(expected),
         ExpectOperation::IsOneOf => expected.split('|').any(|part| part == received),
-        // TODO: This regex could probably be pre-compiled?
-        ExpectOperation::Matches => Regex::new(expected).unwrap().is_match(received),
+        // Regexes from the config are compiled when it's loaded, and checked in validate_expectation
+        ExpectOperation::Matches => {
+            Regex::new(expected).is_ok_and(|regex| regex.is_match(received))
+        }
+        ExpectOperation::LessThan => parse_received(received)? < parse_expected(expected)?,
+        ExpectOperation::LessThanOrEqual => parse_received(received)? <= parse_expected(expected)?,
+        ExpectOperation::GreaterThan => parse_received(received)? > parse_expected(expected)?,
+        ExpectOperation::GreaterThanOrEqual => {
+            parse_received(received)? >= parse_expected(expected)?
+        }
+        ExpectOperation::Between => {
+            let (lower, upper) = parse_range(expected)?;
+            let received = parse_received(received)?;
+            lower <= received && received <= upper
+        }
+        // Schemas and snapshots need more than the expected value, so are checked separately
+        ExpectOperation::MatchesSchema | ExpectOperation::MatchesSnapshot => false,
+    };
+    Ok(met)
+}
+
+fn parse_received(received: &str) -> Result<f64, NumericComparisonError> {
+    received.trim().parse::<f64>().map_err(|_| {
+        let mut preview: String = received.chars().take(MAX_RECEIVED_PREVIEW_CHARS).collect();
+        if preview.len() < received.len() {
+            preview.push_str("...");
+        }
+        NumericComparisonError::ReceivedNotNumeric(Some(preview))
+    })
+}
+
+fn parse_expected(expected: &str) -> Result<f64, NumericComparisonError> {
+    expected
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| NumericComparisonError::ExpectedNotNumeric(expected.to_owned()))
+}
+
+// Ranges are inclusive and written as "lower|upper", e.g. "200|299".
+fn parse_range(expected: &str) -> Result<(f64, f64), NumericComparisonError> {
+    match expected.split_once('|') {
+        Some((lower, upper)) => Ok((parse_expected(lower)?, parse_expected(upper)?)),
+        None => Err(NumericComparisonError::InvalidRange(expected.to_owned())),
     }
 }
 
 fn validate_expectation(
     expect: &ProbeExpectation,
-    status_code: u32,
-    body: &String,
+    response: &EndpointResult,
 ) -> Result<(), ExpectationFailedError> {
     let expected_value = &expect.value;
-    let status_string = status_code.to_string();
-    let received_value = match expect.field {
-        ExpectField::Body => body,
-        ExpectField::StatusCode => &status_string,
+    let received_value = match resolve_field(&expect.field, response) {
+        Ok(value) => value,
+        Err(reason) => return Err(expectation_failed(expect, response, Some(reason))),
     };
-    let success = expectation_met(&expect.operation, expected_value, received_value);
-    if success {
-        Ok(())
-    } else {
-        Err(ExpectationFailedError {
-            expected: expect.value.clone(),
-            body: body.clone(),
-            operation: expect.operation.clone(),
-            field: expect.field.clone(),
-            status_code,
-        })
+    if let (ExpectOperation::Matches, Some(regex)) = (&expect.operation, &expect.compiled.regex) {
+        return if regex.is_match(&received_value) {
+            Ok(())
+        } else {
+            Err(expectation_failed(expect, response, None))
+        };
+    }
+    if let ExpectOperation::MatchesSchema = expect.operation {
+        return match schema_violations(expect, &received_value) {
+            Ok(()) => Ok(()),
+            Err(reason) => Err(expectation_failed(expect, response, Some(reason))),
+        };
+    }
+    match expectation_met(&expect.operation, expected_value, &received_value) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(expectation_failed(expect, response, None)),
+        Err(NumericComparisonError::ReceivedNotNumeric(_)) if response.sensitive => {
+            let e = NumericComparisonError::ReceivedNotNumeric(None);
+            Err(expectation_failed(expect, response, Some(e.to_string())))
+        }
+        Err(e) => Err(expectation_failed(expect, response, Some(e.to_string()))),
+    }
+}
+
+fn resolve_field(field: &ExpectField, response: &EndpointResult) -> Result<String, String> {
+    match field {
+        ExpectField::Body => Ok(response.body.clone()),
+        ExpectField::StatusCode => Ok(response.status_code.to_string()),
+        ExpectField::JsonPath(path) => resolve_json_path(path, &response.body),
+        ExpectField::ResponseTimeMs => Ok(response.response_time_ms().to_string()),
+        ExpectField::XPath(expression) => xpath_value(&response.body, expression),
+        ExpectField::CssSelector(selector) => css_selector_value(&response.body, selector),
+        ExpectField::Header(name) => match response.headers.get(&name.to_lowercase()) {
+            Some(value) => Ok(value.clone()),
+            None => Err(format!(
+                "Header '{}' was not present in the response.",
+                name
+            )),
+        },
+    }
+}
+
+fn expectation_failed(
+    expect: &ProbeExpectation,
+    response: &EndpointResult,
+    reason: Option<String>,
+) -> ExpectationFailedError {
+    ExpectationFailedError {
+        expected: expect.value.clone(),
+        body: response.body.clone(),
+        operation: expect.operation.clone(),
+        field: expect.field.clone(),
+        status_code: response.status_code,
+        reason,
+    }
+}
+
+fn schema_violations(expect: &ProbeExpectation, received: &str) -> Result<(), String> {
+    // Expectations built outside of config loading won't have been compiled yet
+    let uncompiled_schema;
+    let schema = match (&expect.compiled.schema, &expect.schema) {
+        (Some(schema), _) => schema.as_ref(),
+        (None, Some(source)) => {
+            uncompiled_schema = compile_schema(source)?;
+            &uncompiled_schema
+        }
+        (None, None) => return Err("No JSON Schema was configured.".to_owned()),
+    };
+    let instance: Value =
+        serde_json::from_str(received).map_err(|e| format!("Value is not valid JSON: {}.", e))?;
+
+    let result = schema.validate(&instance);
+    if let Err(errors) = result {
+        // Only the paths and keywords are reported, as jsonschema's messages include values
+        // from the response, which may be sensitive
+        let violations: Vec<String> = errors
+            .map(|e| {
+                let path = match e.instance_path.to_string() {
+                    path if path.is_empty() => "/".to_owned(),
+                    path => path,
+                };
+                let schema_path = e.schema_path.to_string();
+                match &e.kind {
+                    // The property name comes from the schema rather than the response
+                    ValidationErrorKind::Required { property } => {
+                        format!("{}: missing required property {}", path, property)
+                    }
+                    _ => format!(
+                        "{}: failed '{}' at {}",
+                        path,
+                        schema_path.rsplit('/').next().unwrap_or_default(),
+                        schema_path
+                    ),
+                }
+            })
+            .collect();
+        let mut reason = format!(
+            "Value does not match the JSON Schema: {}",
+            violations
+                .iter()
+                .take(MAX_REPORTED_SCHEMA_VIOLATIONS)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+        if violations.len() > MAX_REPORTED_SCHEMA_VIOLATIONS {
+            reason.push_str(&format!(
+                " (and {} more)",
+                violations.len() - MAX_REPORTED_SCHEMA_VIOLATIONS
+            ));
+        }
+        return Err(reason);
+    }
+    Ok(())
+}
+
+// Resolves a JSONPath against the response body. A single match is returned as-is
+// (strings unquoted), multiple matches are returned as a JSON array.
+pub fn resolve_json_path(path: &str, body: &str) -> Result<String, String> {
+    let json_path =
+        JsonPath::parse(path).map_err(|e| format!("Invalid JSONPath '{}': {}.", path, e))?;
+    let json_body: Value = serde_json::from_str(body)
+        .map_err(|e| format!("Response body is not valid JSON: {}.", e))?;
+
+    let nodes = json_path.query(&json_body).all();
+    match nodes.as_slice() {
+        [] => Err(format!(
+            "JSONPath '{}' did not resolve to any value in the response body.",
+            path
+        )),
+        [value] => Ok(json_value_to_string(value)),
+        values => Ok(serde_json::to_string(values).unwrap_or_default()),
+    }
+}
+
+fn json_value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => serde_json::to_string(value).unwrap_or_default(),
     }
 }
 
@@ -90,14 +498,16 @@ async fn test_validate_expectations_equals() {
         &ExpectOperation::Equals,
         &"Test".to_owned(),
         &"Test".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(success_result);
 
     let fail_result = expectation_met(
         &ExpectOperation::Equals,
         &"Test123".to_owned(),
         &"Test".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(!fail_result);
 }
 
@@ -107,14 +517,16 @@ async fn test_validate_expectations_not_equals() {
         &ExpectOperation::NotEquals,
         &"Test".to_owned(),
         &"Test123".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(success_result);
 
     let fail_result = expectation_met(
         &ExpectOperation::NotEquals,
         &"Test".to_owned(),
         &"Test".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(!fail_result);
 }
 
@@ -124,14 +536,16 @@ async fn test_validate_expectations_contains() {
         &ExpectOperation::Contains,
         &"Test".to_owned(),
         &"Test123".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(success_result);
 
     let fail_result = expectation_met(
         &ExpectOperation::Contains,
         &"Test123".to_owned(),
         &"Test".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(!fail_result);
 }
 
@@ -141,14 +555,16 @@ async fn test_validate_expectations_not_contains() {
         &ExpectOperation::NotContains,
         &"Test123".to_owned(),
         &"Test".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(success_result);
 
     let fail_result = expectation_met(
         &ExpectOperation::NotContains,
         &"Test".to_owned(),
         &"Test123".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(!fail_result);
 }
 
@@ -158,14 +574,16 @@ async fn test_validate_expectations_isoneof() {
         &ExpectOperation::IsOneOf,
         &"Test|Yes|No".to_owned(),
         &"Test".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(success_result);
 
     let fail_result = expectation_met(
         &ExpectOperation::IsOneOf,
         &"Test|Yes|No".to_owned(),
         &"Yest".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(!fail_result);
 }
 
@@ -175,13 +593,665 @@ async fn test_validate_expectations_matches() {
         &ExpectOperation::Matches,
         &r#"^\d{5}$"#.to_owned(),
         &"12345".to_owned(),
-    );
+    )
+    .unwrap();
     assert!(success_result);
 
     let fail_result = expectation_met(
         &ExpectOperation::Matches,
         &r#"^\d{5}$"#.to_owned(),
         &"1234".to_owned(),
+    )
+    .unwrap();
+    assert!(!fail_result);
+}
+
+#[tokio::test]
+async fn test_validate_expectations_less_than() {
+    let success_result = expectation_met(
+        &ExpectOperation::LessThan,
+        &"500".to_owned(),
+        &"120".to_owned(),
+    )
+    .unwrap();
+    assert!(success_result);
+
+    let fail_result = expectation_met(
+        &ExpectOperation::LessThan,
+        &"500".to_owned(),
+        &"500".to_owned(),
+    )
+    .unwrap();
+    assert!(!fail_result);
+
+    let not_numeric_result = expectation_met(
+        &ExpectOperation::LessThan,
+        &"500".to_owned(),
+        &"fast".to_owned(),
     );
+    assert!(matches!(
+        not_numeric_result,
+        Err(NumericComparisonError::ReceivedNotNumeric(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_validate_expectations_greater_than() {
+    let success_result = expectation_met(
+        &ExpectOperation::GreaterThan,
+        &"10".to_owned(),
+        &"10.5".to_owned(),
+    )
+    .unwrap();
+    assert!(success_result);
+
+    let fail_result = expectation_met(
+        &ExpectOperation::GreaterThan,
+        &"10".to_owned(),
+        &"9".to_owned(),
+    )
+    .unwrap();
+    assert!(!fail_result);
+}
+
+#[tokio::test]
+async fn test_validate_expectations_or_equal() {
+    let success_result = expectation_met(
+        &ExpectOperation::LessThanOrEqual,
+        &"100".to_owned(),
+        &"100".to_owned(),
+    )
+    .unwrap();
+    assert!(success_result);
+
+    let success_result = expectation_met(
+        &ExpectOperation::GreaterThanOrEqual,
+        &"100".to_owned(),
+        &"100".to_owned(),
+    )
+    .unwrap();
+    assert!(success_result);
+
+    let fail_result = expectation_met(
+        &ExpectOperation::GreaterThanOrEqual,
+        &"100".to_owned(),
+        &"99".to_owned(),
+    )
+    .unwrap();
     assert!(!fail_result);
 }
+
+#[tokio::test]
+async fn test_validate_expectations_between() {
+    let success_result = expectation_met(
+        &ExpectOperation::Between,
+        &"200|299".to_owned(),
+        &"204".to_owned(),
+    )
+    .unwrap();
+    assert!(success_result);
+
+    let fail_result = expectation_met(
+        &ExpectOperation::Between,
+        &"200|299".to_owned(),
+        &"301".to_owned(),
+    )
+    .unwrap();
+    assert!(!fail_result);
+
+    let invalid_range_result = expectation_met(
+        &ExpectOperation::Between,
+        &"200".to_owned(),
+        &"204".to_owned(),
+    );
+    assert!(matches!(
+        invalid_range_result,
+        Err(NumericComparisonError::InvalidRange(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_validate_expectation_not_numeric() {
+    let expectation = ProbeExpectation {
+        field: ExpectField::Body,
+        operation: ExpectOperation::LessThan,
+        value: "100".to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    };
+
+    let error = validate_expectation(&expectation, &endpoint_result(200, "many")).unwrap_err();
+    assert_eq!(
+        Some("Received value 'many' is not a number.".to_owned()),
+        error.reason
+    );
+
+    let body = format!("{{\"items\": [{}]}}", "1, ".repeat(50));
+    let error = validate_expectation(&expectation, &endpoint_result(200, &body)).unwrap_err();
+    assert_eq!(
+        Some(format!(
+            "Received value '{}...' is not a number.",
+            &body[..MAX_RECEIVED_PREVIEW_CHARS]
+        )),
+        error.reason
+    );
+
+    let mut sensitive = endpoint_result(200, "token=abc123");
+    sensitive.sensitive = true;
+    let error = validate_expectation(&expectation, &sensitive).unwrap_err();
+    assert_eq!(
+        Some("Received value is not a number.".to_owned()),
+        error.reason
+    );
+}
+
+#[cfg(test)]
+fn endpoint_result(status_code: u32, body: &str) -> EndpointResult {
+    EndpointResult {
+        timestamp_request_started: chrono::Utc::now(),
+        timestamp_response_received: chrono::Utc::now(),
+        status_code,
+        headers: std::collections::HashMap::new(),
+        body: body.to_owned(),
+        trace_id: "".to_owned(),
+        span_id: "".to_owned(),
+        sensitive: false,
+        peer_ip: None,
+        http_version: "1.1".to_owned(),
+    }
+}
+
+#[tokio::test]
+async fn test_validate_expectation_json_path() {
+    let response = endpoint_result(
+        200,
+        r#"{"data": {"items": [{"status": "active", "count": 3}]}}"#,
+    );
+    let expectation = ProbeExpectation {
+        field: ExpectField::JsonPath("$.data.items[0].status".to_owned()),
+        operation: ExpectOperation::Equals,
+        value: "active".to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    };
+    assert!(validate_expectation(&expectation, &response).is_ok());
+
+    let expectation = ProbeExpectation {
+        field: ExpectField::JsonPath("$.data.items[0].count".to_owned()),
+        operation: ExpectOperation::Equals,
+        value: "3".to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    };
+    assert!(validate_expectation(&expectation, &response).is_ok());
+
+    let expectation = ProbeExpectation {
+        field: ExpectField::JsonPath("$.data.items[0].status".to_owned()),
+        operation: ExpectOperation::Equals,
+        value: "inactive".to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    };
+    let error = validate_expectation(&expectation, &response).unwrap_err();
+    assert!(error.reason.is_none());
+}
+
+#[tokio::test]
+async fn test_validate_expectation_json_path_unresolved() {
+    let expectation = ProbeExpectation {
+        field: ExpectField::JsonPath("$.data.missing".to_owned()),
+        operation: ExpectOperation::Equals,
+        value: "active".to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    };
+
+    let error =
+        validate_expectation(&expectation, &endpoint_result(200, r#"{"data": {}}"#)).unwrap_err();
+    assert!(error.to_string().contains("did not resolve"));
+
+    let error = validate_expectation(&expectation, &endpoint_result(200, "not json")).unwrap_err();
+    assert!(error.to_string().contains("not valid JSON"));
+}
+
+#[tokio::test]
+async fn test_validate_expectation_header() {
+    let mut response = endpoint_result(200, "");
+    response
+        .headers
+        .insert("content-type".to_owned(), "application/json".to_owned());
+
+    let expectation = ProbeExpectation {
+        field: ExpectField::Header("Content-Type".to_owned()),
+        operation: ExpectOperation::Contains,
+        value: "json".to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    };
+    assert!(validate_expectation(&expectation, &response).is_ok());
+
+    let expectation = ProbeExpectation {
+        field: ExpectField::Header("x-version".to_owned()),
+        operation: ExpectOperation::Equals,
+        value: "2".to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    };
+    let error = validate_expectation(&expectation, &response).unwrap_err();
+    assert!(error.to_string().contains("was not present"));
+}
+
+#[tokio::test]
+async fn test_json_path_expectation_from_yaml() {
+    let yaml = r#"
+    field:
+      JsonPath: $.data.items[0].status
+    operation: Equals
+    value: active
+    "#;
+    let expectation: ProbeExpectation = serde_yaml::from_str(yaml).unwrap();
+    assert!(matches!(
+        expectation.field,
+        ExpectField::JsonPath(path) if path == "$.data.items[0].status"
+    ));
+}
+
+#[tokio::test]
+async fn test_validate_expectation_response_time() {
+    let mut response = endpoint_result(200, "");
+    response.timestamp_response_received =
+        response.timestamp_request_started + chrono::Duration::milliseconds(9000);
+
+    let expectation = ProbeExpectation {
+        field: ExpectField::ResponseTimeMs,
+        operation: ExpectOperation::LessThan,
+        value: "10000".to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    };
+    assert!(validate_expectation(&expectation, &response).is_ok());
+
+    let expectation = ProbeExpectation {
+        field: ExpectField::ResponseTimeMs,
+        operation: ExpectOperation::LessThan,
+        value: "2000".to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    };
+    assert!(validate_expectation(&expectation, &response).is_err());
+}
+
+#[tokio::test]
+async fn test_validate_expectation_json_schema() {
+    let yaml = r#"
+    field: Body
+    operation: MatchesSchema
+    schema:
+      type: object
+      required: [id, items]
+      properties:
+        id:
+          type: integer
+        items:
+          type: array
+          items:
+            type: string
+    "#;
+    let mut expectations: Option<Vec<Expectation>> =
+        Some(vec![serde_yaml::from_str(yaml).unwrap()]);
+    compile_expectations(&mut expectations, "probe 'test'").unwrap();
+    let Some(Expectation::Field(expectation)) = &expectations.unwrap().pop() else {
+        panic!("Expected a field expectation");
+    };
+    assert!(expectation.compiled.schema.is_some());
+
+    let response = endpoint_result(200, r#"{"id": 1, "items": ["a", "b"]}"#);
+    assert!(validate_expectation(expectation, &response).is_ok());
+
+    let response = endpoint_result(200, r#"{"id": "one", "items": ["a", 2]}"#);
+    let error = validate_expectation(expectation, &response).unwrap_err();
+    let reason = error.reason.unwrap();
+    assert!(
+        reason.contains("/id: failed 'type' at /properties/id/type"),
+        "{}",
+        reason
+    );
+    assert!(reason.contains("/items/1: failed 'type'"), "{}", reason);
+    assert!(!reason.contains("one"), "{}", reason);
+
+    let response = endpoint_result(200, r#"{"id": 1}"#);
+    let reason = validate_expectation(expectation, &response)
+        .unwrap_err()
+        .reason
+        .unwrap();
+    assert!(
+        reason.contains("/: missing required property \"items\""),
+        "{}",
+        reason
+    );
+}
+
+#[tokio::test]
+async fn test_compile_expectations_invalid_schema() {
+    let yaml = r#"
+    field: Body
+    operation: MatchesSchema
+    schema:
+      file: does/not/exist.json
+    "#;
+    let mut expectations: Option<Vec<Expectation>> =
+        Some(vec![serde_yaml::from_str(yaml).unwrap()]);
+    let error = compile_expectations(&mut expectations, "probe 'test'").unwrap_err();
+    assert_eq!("expectations[0]", error.path);
+    assert!(error.to_string().contains("probe 'test'"));
+}
+
+#[cfg(test)]
+fn field_expectation(field: ExpectField, operation: ExpectOperation, value: &str) -> Expectation {
+    Expectation::Field(ProbeExpectation {
+        field,
+        operation,
+        value: value.to_owned(),
+        schema: None,
+        ignore_paths: vec![],
+        compiled: Default::default(),
+    })
+}
+
+#[tokio::test]
+async fn test_validate_response_reports_all_failures() {
+    let expectations = vec![
+        field_expectation(ExpectField::StatusCode, ExpectOperation::Equals, "200"),
+        field_expectation(ExpectField::Body, ExpectOperation::Contains, "ok"),
+        field_expectation(ExpectField::Body, ExpectOperation::Contains, "error"),
+    ];
+
+    let error = validate_response_internal(
+        &expectations,
+        &endpoint_result(500, "error"),
+        &Snapshots::new(),
+        "test",
+    )
+    .unwrap_err();
+    assert_eq!(2, error.failures.len());
+    assert!(error.to_string().starts_with("2 expectations failed"));
+}
+
+#[tokio::test]
+async fn test_validate_response_any_of() {
+    let yaml = r#"
+    - any_of:
+        - all_of:
+            - field: StatusCode
+              operation: Equals
+              value: "200"
+            - field:
+                JsonPath: $.id
+              operation: Equals
+              value: "1"
+        - field: StatusCode
+          operation: Equals
+          value: "204"
+    "#;
+    let expectations: Vec<Expectation> = serde_yaml::from_str(yaml).unwrap();
+
+    assert!(validate_response_internal(
+        &expectations,
+        &endpoint_result(200, r#"{"id": 1}"#),
+        &Snapshots::new(),
+        "test"
+    )
+    .is_ok());
+    assert!(validate_response_internal(
+        &expectations,
+        &endpoint_result(204, ""),
+        &Snapshots::new(),
+        "test"
+    )
+    .is_ok());
+
+    let error = validate_response_internal(
+        &expectations,
+        &endpoint_result(200, ""),
+        &Snapshots::new(),
+        "test",
+    )
+    .unwrap_err();
+    assert_eq!(1, error.failures.len());
+    assert!(
+        matches!(&error.failures[0], ExpectationFailure::AnyOf(failures) if failures.len() == 2)
+    );
+}
+
+#[tokio::test]
+async fn test_validate_response_not() {
+    let expectations = vec![Expectation::Not {
+        not: Box::new(field_expectation(
+            ExpectField::Body,
+            ExpectOperation::Contains,
+            "error",
+        )),
+    }];
+
+    assert!(validate_response_internal(
+        &expectations,
+        &endpoint_result(200, "ok"),
+        &Snapshots::new(),
+        "test"
+    )
+    .is_ok());
+
+    let error = validate_response_internal(
+        &expectations,
+        &endpoint_result(200, "an error"),
+        &Snapshots::new(),
+        "test",
+    )
+    .unwrap_err();
+    assert!(matches!(&error.failures[0], ExpectationFailure::Not(_)));
+}
+
+#[tokio::test]
+async fn test_expectation_group_reports_field_errors() {
+    let yaml = r#"
+    - any_of:
+        - field: StatusCode
+          operation: Equal
+          value: "200"
+    "#;
+    let error = serde_yaml::from_str::<Vec<Expectation>>(yaml).unwrap_err();
+    assert!(error.to_string().contains("Equal"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_compile_expectations_regex() {
+    let mut expectations = Some(vec![
+        field_expectation(ExpectField::StatusCode, ExpectOperation::Equals, "200"),
+        Expectation::AnyOf {
+            any_of: vec![field_expectation(
+                ExpectField::Body,
+                ExpectOperation::Matches,
+                r#"^\d{5}$"#,
+            )],
+        },
+    ]);
+    compile_expectations(&mut expectations, "probe 'test'").unwrap();
+
+    let expectations = expectations.unwrap();
+    let Expectation::AnyOf { any_of } = &expectations[1] else {
+        panic!("Expected an any_of expectation");
+    };
+    let Expectation::Field(expectation) = &any_of[0] else {
+        panic!("Expected a field expectation");
+    };
+    assert!(expectation.compiled.regex.is_some());
+    assert!(validate_response_internal(
+        &expectations,
+        &endpoint_result(200, "12345"),
+        &Snapshots::new(),
+        "test"
+    )
+    .is_ok());
+    assert!(validate_response_internal(
+        &expectations,
+        &endpoint_result(200, "1234"),
+        &Snapshots::new(),
+        "test"
+    )
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_compile_expectations_invalid_regex() {
+    let mut expectations = Some(vec![
+        field_expectation(ExpectField::StatusCode, ExpectOperation::Equals, "200"),
+        field_expectation(ExpectField::Body, ExpectOperation::Matches, "(unclosed"),
+    ]);
+
+    let error =
+        compile_expectations(&mut expectations, "step 'login' in story 'users'").unwrap_err();
+    assert_eq!("expectations[1]", error.path);
+    assert!(error
+        .to_string()
+        .starts_with("Invalid expectation at expectations[1] for step 'login' in story 'users'"));
+
+    let yaml = r#"
+    - field: StatusCode
+      operation: Equals
+      value: "200"
+    - any_of:
+        - field: StatusCode
+          operation: Equals
+          value: "204"
+        - all_of:
+            - not:
+                field: Body
+                operation: Matches
+                value: "(unclosed"
+    "#;
+    let mut expectations = Some(serde_yaml::from_str::<Vec<Expectation>>(yaml).unwrap());
+    let error = compile_expectations(&mut expectations, "probe 'test'").unwrap_err();
+    assert_eq!("expectations[1].any_of[1].all_of[0].not", error.path);
+}
+
+#[tokio::test]
+async fn test_snapshot_recorded_then_compared() {
+    let snapshots = Snapshots::new();
+    let expectations = vec![Expectation::Field(ProbeExpectation {
+        field: ExpectField::Body,
+        operation: ExpectOperation::MatchesSnapshot,
+        value: "".to_owned(),
+        schema: None,
+        ignore_paths: vec!["$.updated_at".to_owned()],
+        compiled: Default::default(),
+    })];
+
+    let first = endpoint_result(200, r#"{"flags": {"beta": true}, "updated_at": 1}"#);
+    assert!(validate_response_internal(&expectations, &first, &snapshots, "test").is_ok());
+    assert!(snapshots.get("test#0").is_some());
+
+    let same_shape = endpoint_result(200, r#"{"flags": {"beta": false}, "updated_at": "now"}"#);
+    assert!(validate_response_internal(&expectations, &same_shape, &snapshots, "test").is_ok());
+
+    let drifted = endpoint_result(200, r#"{"flags": {"beta": "yes", "new": true}}"#);
+    let error = validate_response_internal(&expectations, &drifted, &snapshots, "test")
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("/flags/beta: type changed from boolean to string"),
+        "{}",
+        error
+    );
+    assert!(error.contains("/flags/new: key added"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_snapshot_not_recorded_when_other_expectations_fail() {
+    let snapshots = Snapshots::new();
+    let expectations = vec![
+        field_expectation(ExpectField::StatusCode, ExpectOperation::Equals, "200"),
+        field_expectation(ExpectField::Body, ExpectOperation::MatchesSnapshot, ""),
+    ];
+
+    let response = endpoint_result(500, r#"{"error": "unavailable"}"#);
+    assert!(validate_response_internal(&expectations, &response, &snapshots, "test").is_err());
+    assert!(snapshots.get("test#1").is_none());
+}
+
+#[tokio::test]
+async fn test_xpath_expectation_with_namespaces() {
+    let soap = endpoint_result(
+        200,
+        r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/">
+  <soap:Body><m:GetOrderResponse xmlns:m="http://example.com/orders">
+    <m:Status>shipped</m:Status>
+  </m:GetOrderResponse></soap:Body>
+</soap:Envelope>"#,
+    );
+    let mut expectations = Some(vec![field_expectation(
+        ExpectField::XPath("/soap:Envelope/soap:Body/m:GetOrderResponse/m:Status".to_owned()),
+        ExpectOperation::Equals,
+        "shipped",
+    )]);
+    compile_expectations(&mut expectations, "probe 'test'").unwrap();
+    assert!(
+        validate_response_internal(&expectations.unwrap(), &soap, &Snapshots::new(), "test")
+            .is_ok()
+    );
+
+    let expectation = field_expectation(
+        ExpectField::XPath("/s:Envelope".to_owned()),
+        ExpectOperation::Equals,
+        "shipped",
+    );
+    let error = validate_response_internal(&[expectation], &soap, &Snapshots::new(), "test")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("namespace prefix 's'"), "{}", error);
+
+    let mut invalid = Some(vec![field_expectation(
+        ExpectField::XPath("//[".to_owned()),
+        ExpectOperation::Equals,
+        "shipped",
+    )]);
+    let error = compile_expectations(&mut invalid, "probe 'test'").unwrap_err();
+    assert!(error.reason.starts_with("Invalid XPath"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_xpath_and_css_selector_expectations() {
+    let xml = endpoint_result(200, "<Response><Status>OK</Status></Response>");
+    let expectation = field_expectation(
+        ExpectField::XPath("/Response/Status".to_owned()),
+        ExpectOperation::Equals,
+        "OK",
+    );
+    assert!(validate_response_internal(&[expectation], &xml, &Snapshots::new(), "test").is_ok());
+
+    let html = endpoint_result(200, "<html><body><p id=\"total\">12</p></body></html>");
+    let expectation = field_expectation(
+        ExpectField::CssSelector("p#total".to_owned()),
+        ExpectOperation::GreaterThan,
+        "10",
+    );
+    assert!(validate_response_internal(&[expectation], &html, &Snapshots::new(), "test").is_ok());
+
+    let expectation = field_expectation(
+        ExpectField::CssSelector("p#missing".to_owned()),
+        ExpectOperation::Equals,
+        "12",
+    );
+    let error = validate_response_internal(&[expectation], &html, &Snapshots::new(), "test")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("did not match any elements"), "{}", error);
+}
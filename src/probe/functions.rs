@@ -0,0 +1,114 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+
+// Everything except the unreserved characters from RFC 3986
+const URL_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// Applies a template function, e.g. `${{ vars.user | base64 }}` or
+// `${{ hmac_sha256(vars.key, request.body) }}`. Hashes are returned as lowercase hex.
+pub fn apply_function(name: &str, args: &[String]) -> Result<String, String> {
+    match (name, args) {
+        ("base64", [value]) => Ok(STANDARD.encode(value)),
+        ("urlencode", [value]) => Ok(urlencode(value)),
+        ("sha256", [value]) => Ok(to_hex(&Sha256::digest(value))),
+        ("hmac_sha256", [key, message]) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+                .map_err(|e| format!("Invalid key for hmac_sha256: {}", e))?;
+            mac.update(message.as_bytes());
+            Ok(to_hex(&mac.finalize().into_bytes()))
+        }
+        ("lower", [value]) => Ok(value.to_lowercase()),
+        ("upper", [value]) => Ok(value.to_uppercase()),
+        ("json_escape", [value]) => {
+            let quoted = serde_json::to_string(value).map_err(|e| e.to_string())?;
+            Ok(quoted[1..quoted.len() - 1].to_owned())
+        }
+        ("base64" | "urlencode" | "sha256" | "lower" | "upper" | "json_escape", _) => Err(format!(
+            "Function '{}' takes 1 argument, got {}.",
+            name,
+            args.len()
+        )),
+        ("hmac_sha256", _) => Err(format!(
+            "Function 'hmac_sha256' takes 2 arguments, got {}.",
+            args.len()
+        )),
+        _ => Err(format!("Unknown function '{}'.", name)),
+    }
+}
+
+pub fn urlencode(value: &str) -> String {
+    utf8_percent_encode(value, URL_ENCODE_SET).to_string()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod function_tests {
+    use super::*;
+
+    fn apply(name: &str, args: &[&str]) -> Result<String, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        apply_function(name, &args)
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!("dXNlcjpwYXNz", apply("base64", &["user:pass"]).unwrap());
+        assert_eq!("", apply("base64", &[""]).unwrap());
+    }
+
+    #[test]
+    fn test_urlencode() {
+        assert_eq!(
+            "a%20b%26c%3Dd~e.f_g-h%2F%C3%A9",
+            apply("urlencode", &["a b&c=d~e.f_g-h/é"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            apply("sha256", &["abc"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // Test case 2 from RFC 4231
+        assert_eq!(
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            apply("hmac_sha256", &["Jefe", "what do ya want for nothing?"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_lower_and_upper() {
+        assert_eq!("mixed case", apply("lower", &["MiXeD Case"]).unwrap());
+        assert_eq!("MIXED CASE", apply("upper", &["MiXeD Case"]).unwrap());
+    }
+
+    #[test]
+    fn test_json_escape() {
+        assert_eq!(
+            r#"say \"hi\"\n\\ done"#,
+            apply("json_escape", &["say \"hi\"\n\\ done"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_functions() {
+        assert!(apply("base64", &["a", "b"]).is_err());
+        assert!(apply("hmac_sha256", &["key"]).is_err());
+        assert!(apply("unknown", &["a"]).is_err());
+    }
+}
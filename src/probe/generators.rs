@@ -0,0 +1,183 @@
+use chrono::{Duration, SecondsFormat, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use uuid::Uuid;
+
+const DEFAULT_STRING_LENGTH: usize = 16;
+
+// Generates a value for `${{generate.<expression>}}`. Expressions are a name, optionally
+// followed by arguments in brackets, e.g. `uuid`, `int(1,100)` or `timestamp(+1h)`.
+pub fn generate_value(expression: &str) -> Result<String, String> {
+    let (name, args) = parse_expression(expression)?;
+
+    match (name, args.as_slice()) {
+        ("uuid", []) => Ok(Uuid::new_v4().to_string()),
+        ("timestamp", args) => {
+            Ok((Utc::now() + parse_offset(args)?).to_rfc3339_opts(SecondsFormat::Millis, true))
+        }
+        ("epoch", args) => Ok((Utc::now() + parse_offset(args)?).timestamp().to_string()),
+        ("epoch_ms", args) => Ok((Utc::now() + parse_offset(args)?)
+            .timestamp_millis()
+            .to_string()),
+        ("date", args) => Ok((Utc::now() + parse_offset(args)?)
+            .format("%Y-%m-%d")
+            .to_string()),
+        ("int", [min, max]) => {
+            let min = parse_number::<i64>(min)?;
+            let max = parse_number::<i64>(max)?;
+            if min > max {
+                return Err(format!("Invalid range {}..{} for int.", min, max));
+            }
+            Ok(rand::thread_rng().gen_range(min..=max).to_string())
+        }
+        ("string", []) => Ok(random_string(DEFAULT_STRING_LENGTH)),
+        ("string", [length]) => Ok(random_string(parse_number::<usize>(length)?)),
+        ("email", []) => Ok(format!(
+            "prodzilla-{}@example.com",
+            random_string(12).to_lowercase()
+        )),
+        ("uuid" | "int" | "string" | "email", _) => Err(format!(
+            "Wrong number of arguments for generated value '{}'.",
+            name
+        )),
+        _ => Err(format!("Unknown generated value '{}'.", name)),
+    }
+}
+
+fn parse_expression(expression: &str) -> Result<(&str, Vec<&str>), String> {
+    match expression.split_once('(') {
+        None => Ok((expression.trim(), vec![])),
+        Some((name, rest)) => {
+            let args = rest
+                .strip_suffix(')')
+                .ok_or_else(|| format!("Missing ')' in generated value '{}'.", expression))?;
+            let args = match args.trim() {
+                "" => vec![],
+                args => args.split(',').map(str::trim).collect(),
+            };
+            Ok((name.trim(), args))
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("Invalid number '{}'.", value))
+}
+
+// Offsets are a signed amount of seconds, minutes, hours or days, e.g. `+1h` or `-30m`
+fn parse_offset(args: &[&str]) -> Result<Duration, String> {
+    let offset = match args {
+        [] => return Ok(Duration::zero()),
+        [offset] => *offset,
+        _ => return Err("Timestamps take at most one offset.".to_owned()),
+    };
+    let invalid = || {
+        format!(
+            "Invalid offset '{}', expected e.g. '+1h' or '-30m'.",
+            offset
+        )
+    };
+
+    let (negative, rest) = match offset.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, offset.strip_prefix('+').unwrap_or(offset)),
+    };
+    if rest.len() < 2 {
+        return Err(invalid());
+    }
+    let (amount, unit) = rest.split_at(rest.len() - 1);
+    let amount = amount.parse::<i64>().map_err(|_| invalid())?;
+    let duration = match unit {
+        "s" => Duration::seconds(amount),
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        _ => return Err(invalid()),
+    };
+    Ok(if negative { -duration } else { duration })
+}
+
+fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod generator_tests {
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::*;
+
+    #[test]
+    fn test_generate_uuid() {
+        let value = generate_value("uuid").unwrap();
+        assert!(Uuid::parse_str(&value).is_ok());
+    }
+
+    #[test]
+    fn test_generate_timestamp_with_offset() {
+        let now = generate_value("timestamp").unwrap();
+        let later = generate_value("timestamp(+1h)").unwrap();
+        let now = DateTime::parse_from_rfc3339(&now).unwrap();
+        let later = DateTime::parse_from_rfc3339(&later).unwrap();
+
+        let difference = later - now;
+        assert!(difference >= Duration::hours(1));
+        assert!(difference < Duration::hours(1) + Duration::seconds(5));
+    }
+
+    #[test]
+    fn test_generate_epoch_with_offset() {
+        let now = Utc::now().timestamp();
+        let earlier = generate_value("epoch(-30m)")
+            .unwrap()
+            .parse::<i64>()
+            .unwrap();
+        assert!((now - 1800 - earlier).abs() <= 1);
+
+        let epoch_ms = generate_value("epoch_ms").unwrap().parse::<i64>().unwrap();
+        assert!((Utc::now().timestamp_millis() - epoch_ms).abs() < 5000);
+
+        let tomorrow = (Utc::now() + Duration::days(1))
+            .format("%Y-%m-%d")
+            .to_string();
+        assert_eq!(tomorrow, generate_value("date(+1d)").unwrap());
+    }
+
+    #[test]
+    fn test_generate_int() {
+        for _ in 0..100 {
+            let value = generate_value("int(1, 3)").unwrap().parse::<i64>().unwrap();
+            assert!((1..=3).contains(&value));
+        }
+        assert_eq!("-5", generate_value("int(-5,-5)").unwrap());
+        assert!(generate_value("int(10,1)").is_err());
+        assert!(generate_value("int(a,1)").is_err());
+        assert!(generate_value("int(1)").is_err());
+    }
+
+    #[test]
+    fn test_generate_string_and_email() {
+        let value = generate_value("string(12)").unwrap();
+        assert_eq!(12, value.len());
+        assert!(value.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(16, generate_value("string").unwrap().len());
+
+        let email = generate_value("email").unwrap();
+        assert!(email.starts_with("prodzilla-"));
+        assert!(email.ends_with("@example.com"));
+    }
+
+    #[test]
+    fn test_generate_invalid() {
+        assert!(generate_value("unknown").is_err());
+        assert!(generate_value("timestamp(+1y)").is_err());
+        assert!(generate_value("timestamp(+h)").is_err());
+        assert!(generate_value("int(1,2").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::{Html, Selector};
+use sxd_document::dom::{ChildOfElement, ChildOfRoot, Element};
+use sxd_xpath::{Context, Factory, Value, XPath};
+
+lazy_static! {
+    static ref STRING_LITERAL_REGEX: Regex = Regex::new(r#"'[^']*'|"[^"]*""#).unwrap();
+    // A prefixed name such as `soap:Body` or `soap:*`, but not an axis such as `child::`
+    static ref PREFIX_REGEX: Regex =
+        Regex::new(r"(?:^|::|[^\w.:-])([A-Za-z_][\w.-]*):[A-Za-z_*]").unwrap();
+}
+
+// Parses an XPath expression. This is also used to check expressions when the config is
+// loaded.
+pub fn parse_xpath(expression: &str) -> Result<XPath, String> {
+    Factory::new()
+        .build(expression)
+        .map_err(|e| format!("Invalid XPath '{}': {}.", expression, e))?
+        .ok_or_else(|| format!("Invalid XPath '{}': expression is empty.", expression))
+}
+
+// Evaluates an XPath expression against an XML body. When the expression selects
+// nodes, the text of the first node is returned. Namespace prefixes declared in the
+// body, such as `soap` in `xmlns:soap="..."`, can be used in the expression.
+pub fn xpath_value(body: &str, expression: &str) -> Result<String, String> {
+    let package = sxd_document::parser::parse(body)
+        .map_err(|e| format!("Value is not valid XML: {:?}.", e))?;
+    let document = package.as_document();
+    let xpath = parse_xpath(expression)?;
+
+    let mut namespaces = HashMap::new();
+    for child in document.root().children() {
+        if let ChildOfRoot::Element(element) = child {
+            collect_namespaces(element, &mut namespaces);
+        }
+    }
+    // sxd_xpath panics on prefixes it doesn't know, so they're checked first
+    let literals_removed = STRING_LITERAL_REGEX.replace_all(expression, "''");
+    for prefix in PREFIX_REGEX.captures_iter(&literals_removed) {
+        if !namespaces.contains_key(&prefix[1]) {
+            return Err(format!(
+                "XPath '{}' uses the namespace prefix '{}', which isn't declared in the response.",
+                expression, &prefix[1]
+            ));
+        }
+    }
+    let mut context = Context::new();
+    for (prefix, uri) in &namespaces {
+        context.set_namespace(prefix, uri);
+    }
+
+    let value = xpath
+        .evaluate(&context, document.root())
+        .map_err(|e| format!("Failed to evaluate XPath '{}': {}.", expression, e))?;
+
+    match value {
+        Value::Nodeset(nodes) => match nodes.document_order_first() {
+            Some(node) => Ok(node.string_value()),
+            None => Err(format!("XPath '{}' did not match any nodes.", expression)),
+        },
+        other => Ok(other.string()),
+    }
+}
+
+// Where a prefix is declared more than once, the first declaration in the document is used
+fn collect_namespaces(element: Element, namespaces: &mut HashMap<String, String>) {
+    for namespace in element.namespaces_in_scope() {
+        namespaces
+            .entry(namespace.prefix().to_owned())
+            .or_insert_with(|| namespace.uri().to_owned());
+    }
+    for child in element.children() {
+        if let ChildOfElement::Element(child) = child {
+            collect_namespaces(child, namespaces);
+        }
+    }
+}
+
+// Returns the text of the first HTML element matching a CSS selector, with
+// surrounding whitespace removed.
+pub fn css_selector_value(body: &str, selector: &str) -> Result<String, String> {
+    let parsed_selector = Selector::parse(selector)
+        .map_err(|e| format!("Invalid CSS selector '{}': {}.", selector, e))?;
+    let document = Html::parse_document(body);
+
+    match document.select(&parsed_selector).next() {
+        Some(element) => Ok(element.text().collect::<String>().trim().to_owned()),
+        None => Err(format!(
+            "CSS selector '{}' did not match any elements.",
+            selector
+        )),
+    }
+}
+
+#[cfg(test)]
+mod markup_tests {
+    use super::*;
+
+    const XML: &str = r#"<?xml version="1.0"?>
+<Envelope>
+  <Body>
+    <Order id="42"><Status>shipped</Status></Order>
+    <Order id="43"><Status>pending</Status></Order>
+  </Body>
+</Envelope>"#;
+
+    const HTML: &str = r#"<html><body>
+  <h1 class="title"> Welcome </h1>
+  <ul><li class="item">first</li><li class="item">second</li></ul>
+</body></html>"#;
+
+    #[test]
+    fn test_xpath_value() {
+        assert_eq!("shipped", xpath_value(XML, "//Order/Status").unwrap());
+        assert_eq!("43", xpath_value(XML, "//Order[2]/@id").unwrap());
+        assert_eq!("2", xpath_value(XML, "count(//Order)").unwrap());
+    }
+
+    #[test]
+    fn test_xpath_value_errors() {
+        assert!(xpath_value(XML, "//Missing").is_err());
+        assert!(xpath_value(XML, "//[").is_err());
+        assert!(xpath_value("not xml", "//Order").is_err());
+    }
+
+    const SOAP: &str = r#"<?xml version="1.0"?>
+<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/">
+  <soap:Body>
+    <m:GetOrderResponse xmlns:m="http://example.com/orders">
+      <m:Status>shipped</m:Status>
+    </m:GetOrderResponse>
+  </soap:Body>
+</soap:Envelope>"#;
+
+    #[test]
+    fn test_xpath_value_with_namespaces() {
+        assert_eq!(
+            "shipped",
+            xpath_value(SOAP, "/soap:Envelope/soap:Body/m:GetOrderResponse/m:Status").unwrap()
+        );
+        assert_eq!("shipped", xpath_value(SOAP, "//m:Status").unwrap());
+        assert_eq!(
+            "shipped",
+            xpath_value(SOAP, "//*[local-name()='Status']").unwrap()
+        );
+        assert_eq!(
+            "1",
+            xpath_value(SOAP, "count(child::soap:Envelope)").unwrap()
+        );
+        assert!(xpath_value(SOAP, "count(child::env:Envelope)").is_err());
+        assert_eq!("a:b", xpath_value(SOAP, "string('a:b')").unwrap());
+
+        let error = xpath_value(SOAP, "/env:Envelope/env:Body").unwrap_err();
+        assert!(error.contains("namespace prefix 'env'"), "{}", error);
+    }
+
+    #[test]
+    fn test_css_selector_value() {
+        assert_eq!("Welcome", css_selector_value(HTML, "h1.title").unwrap());
+        assert_eq!("first", css_selector_value(HTML, "ul li.item").unwrap());
+        assert!(css_selector_value(HTML, "p.missing").is_err());
+        assert!(css_selector_value(HTML, "h1[").is_err());
+    }
+}
@@ -1,6 +1,11 @@
+pub(crate) mod auth;
 pub(crate) mod expectations;
+pub(crate) mod functions;
+pub(crate) mod generators;
 pub(crate) mod http_probe;
+pub(crate) mod markup;
 pub(crate) mod model;
 pub(crate) mod probe_logic;
 pub(crate) mod schedule;
+pub(crate) mod snapshots;
 pub(crate) mod variables;
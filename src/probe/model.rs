@@ -1,7 +1,11 @@
 use chrono::{DateTime, Utc};
 
-use serde::{Deserialize, Serialize};
+use jsonschema::JSONSchema;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Probe {
@@ -9,27 +13,269 @@ pub struct Probe {
     pub url: String,
     pub http_method: String,
     pub with: Option<ProbeInputParameters>,
-    pub expectations: Option<Vec<ProbeExpectation>>,
+    pub expectations: Option<Vec<Expectation>>,
     pub schedule: ProbeScheduleParameters,
     pub alerts: Option<Vec<ProbeAlert>>,
     #[serde(default)] // default to false
     pub sensitive: bool,
+    #[serde(
+        default,
+        with = "serde_yaml::with::singleton_map",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub auth: Option<Auth>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<ClientOptions>,
+    // curl-style address overrides, from `host:port` to comma separated IPs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolve: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_family: Option<IpFamily>,
     pub tags: Option<HashMap<String, String>>,
+    // Usable as ${{vars.name}}, overriding global variables with the same name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ProbeInputParameters {
     #[serde(default)]
     pub headers: Option<HashMap<String, String>>,
     pub body: Option<String>,
+    // Alternatives to body, which set the Content-Type header automatically
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub form: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multipart: Option<Vec<MultipartPart>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_file: Option<String>,
+    // The contents of body_file with variables substituted, read along with the other
+    // variables of the probe or step
+    #[serde(skip)]
+    pub body_file_contents: Option<Vec<u8>>,
+    // Appended to the url, percent-encoded after variables are substituted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<HashMap<String, String>>,
     pub timeout_seconds: Option<u64>,
 }
 
+// Parts are sent in the order they're listed, and a name can be repeated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartPart {
+    pub name: String,
+    #[serde(flatten)]
+    pub content: MultipartContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MultipartContent {
+    Text { value: String },
+    File(MultipartFile),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartFile {
+    pub file: String,
+    // Defaults to the name of the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    // Defaults to a type guessed from the file extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+// Sets the Authorization header of a request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Auth {
+    Basic { username: String, password: String },
+    Bearer { token: String },
+    Oauth2ClientCredentials(OAuth2ClientCredentials),
+}
+
+// Tokens are cached until they expire, and shared by every probe and step using the
+// same credentials and scopes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuth2ClientCredentials {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+// Requests with TLS options get their own client, shared by every probe and step with
+// the same options
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TlsOptions {
+    // PEM files, where the key is in PKCS#8 format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    // PEM file of certificates trusted as well as the system's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<TlsVersion>,
+    // Sent as SNI and the Host header, and checked against the server's certificate,
+    // instead of the url's host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+}
+
+// TLS 1.3 can't be required, as the native TLS backend doesn't support it as a minimum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.0")]
+    Tls1_0,
+    #[serde(rename = "1.1")]
+    Tls1_1,
+    #[serde(rename = "1.2")]
+    Tls1_2,
+}
+
+// Like TLS options, requests with client options share a client with the same options
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ClientOptions {
+    // Redirects are followed by default, up to 10 of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub follow_redirects: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_redirects: Option<usize>,
+    // Used for both http and https urls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    // Defaults to HTTP/1.1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_version: Option<HttpVersion>,
+    // Sends Accept-Encoding for gzip, brotli and deflate, and decompresses the response
+    #[serde(default)]
+    pub compression: bool,
+    // Keeps connections open between runs, rather than opening one per request
+    #[serde(default)]
+    pub keepalive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HttpVersion {
+    #[serde(rename = "1.1")]
+    Http1_1,
+    // Sent without negotiating first, so the server must support HTTP/2
+    #[serde(rename = "2")]
+    Http2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl IpFamily {
+    pub fn matches(&self, ip: &IpAddr) -> bool {
+        match self {
+            IpFamily::Ipv4 => ip.is_ipv4(),
+            IpFamily::Ipv6 => ip.is_ipv6(),
+        }
+    }
+}
+
+// Expectations can be grouped with all_of, any_of and not, and groups can be nested.
+// A plain list of expectations must all be met, the same as all_of.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Expectation {
+    AllOf { all_of: Vec<Expectation> },
+    AnyOf { any_of: Vec<Expectation> },
+    Not { not: Box<Expectation> },
+    Field(ProbeExpectation),
+}
+
+// Written by hand rather than with serde(untagged), so that a mistake in a field
+// expectation reports the actual problem instead of a generic untagged enum error
+impl<'de> Deserialize<'de> for Expectation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = serde_yaml::Value::deserialize(deserializer)?;
+        let expectation = if let Some(all_of) = value.get("all_of") {
+            Vec::deserialize(all_of).map(|all_of| Expectation::AllOf { all_of })
+        } else if let Some(any_of) = value.get("any_of") {
+            Vec::deserialize(any_of).map(|any_of| Expectation::AnyOf { any_of })
+        } else if let Some(not) = value.get("not") {
+            Expectation::deserialize(not).map(|not| Expectation::Not { not: Box::new(not) })
+        } else {
+            ProbeExpectation::deserialize(value).map(Expectation::Field)
+        };
+        expectation.map_err(de::Error::custom)
+    }
+}
+
+impl std::fmt::Display for Expectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let join = |expectations: &Vec<Expectation>| {
+            expectations
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Expectation::AllOf { all_of } => write!(f, "all_of [{}]", join(all_of)),
+            Expectation::AnyOf { any_of } => write!(f, "any_of [{}]", join(any_of)),
+            Expectation::Not { not } => write!(f, "not {}", not),
+            Expectation::Field(expectation) => write!(
+                f,
+                "'{:?}' {:?} {:?}",
+                expectation.field, expectation.operation, expectation.value
+            ),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProbeExpectation {
+    #[serde(with = "serde_yaml::with::singleton_map")]
     pub field: ExpectField,
     pub operation: ExpectOperation,
+    #[serde(default)]
     pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<JsonSchemaSource>,
+    // JSONPaths left out of snapshot comparisons, e.g. timestamps and ids
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore_paths: Vec<String>,
+    #[serde(skip)]
+    pub compiled: CompiledExpectation,
+}
+
+// A JSON Schema is either written inline in the config, or loaded from a file with `file: path`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum JsonSchemaSource {
+    File(JsonSchemaFile),
+    Inline(serde_json::Value),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonSchemaFile {
+    pub file: String,
+}
+
+// Built when the config is loaded, so it isn't rebuilt on every probe run
+#[derive(Debug, Clone, Default)]
+pub struct CompiledExpectation {
+    pub regex: Option<Regex>,
+    pub schema: Option<Arc<JSONSchema>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,12 +286,24 @@ pub enum ExpectOperation {
     Contains,
     NotContains,
     Matches,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Between,
+    MatchesSchema,
+    MatchesSnapshot,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ExpectField {
     Body,
     StatusCode,
+    JsonPath(String),
+    Header(String),
+    ResponseTimeMs,
+    XPath(String),
+    CssSelector(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,6 +328,9 @@ pub struct ProbeResult {
     pub response: Option<ProbeResponse>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub trace_id: Option<String>,
+    // The IP address the request was sent to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_ip: Option<String>,
 }
 
 // todo track application errors
@@ -78,6 +339,8 @@ pub struct ProbeResult {
 pub struct ProbeResponse {
     pub timestamp_received: DateTime<Utc>,
     pub status_code: u32,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
     pub body: String,
     pub sensitive: bool,
 }
@@ -95,6 +358,14 @@ pub struct Story {
     pub schedule: ProbeScheduleParameters,
     pub alerts: Option<Vec<ProbeAlert>>,
     pub tags: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict_variables: Option<bool>,
+    // Keeps cookies set by responses and sends them with later steps of the same run
+    #[serde(default)]
+    pub cookie_jar: bool,
+    // Usable as ${{vars.name}}, overriding global variables with the same name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables: Option<HashMap<String, String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -103,9 +374,48 @@ pub struct Step {
     pub url: String,
     pub http_method: String,
     pub with: Option<ProbeInputParameters>,
-    pub expectations: Option<Vec<ProbeExpectation>>,
+    pub expectations: Option<Vec<Expectation>>,
     #[serde(default)] // default to false
     pub sensitive: bool,
+    #[serde(
+        default,
+        with = "serde_yaml::with::singleton_map",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub auth: Option<Auth>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<ClientOptions>,
+    // curl-style address overrides, from `host:port` to comma separated IPs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolve: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_family: Option<IpFamily>,
+    // Named values taken from the response, usable in later steps as ${{vars.name}}
+    #[serde(
+        default,
+        with = "serde_yaml::with::singleton_map_recursive",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub extract: Option<HashMap<String, ExtractSource>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ExtractSource {
+    JsonPath(String),
+    // Applied to the body. Uses the first capture group if there is one, else the whole match
+    Regex(ExtractRegex),
+    Header(String),
+}
+
+// Written as the pattern alone, and compiled when the config is loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ExtractRegex {
+    pub pattern: String,
+    #[serde(skip)]
+    pub compiled: Option<Regex>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -126,26 +436,41 @@ pub struct StepResult {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub response: Option<ProbeResponse>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub extracted: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub trace_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub span_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_ip: Option<String>,
 }
 
 pub struct EndpointResult {
     pub timestamp_request_started: DateTime<Utc>,
     pub timestamp_response_received: DateTime<Utc>,
     pub status_code: u32,
+    pub headers: HashMap<String, String>,
     pub body: String,
     pub trace_id: String,
     pub span_id: String,
     pub sensitive: bool,
+    pub peer_ip: Option<String>,
+    // The HTTP version of the response, e.g. `1.1` or `2`
+    pub http_version: String,
 }
 
 impl EndpointResult {
+    pub fn response_time_ms(&self) -> i64 {
+        self.timestamp_response_received
+            .signed_duration_since(self.timestamp_request_started)
+            .num_milliseconds()
+    }
+
     pub fn to_probe_response(&self) -> ProbeResponse {
         ProbeResponse {
             timestamp_received: self.timestamp_response_received,
             status_code: self.status_code,
+            headers: self.headers.clone(),
             body: self.body.clone(),
             sensitive: self.sensitive,
         }
@@ -4,6 +4,7 @@ use chrono::Utc;
 use opentelemetry::global;
 use opentelemetry::trace;
 use opentelemetry::trace::FutureExt;
+use opentelemetry::trace::SpanRef;
 use opentelemetry::trace::Status;
 use opentelemetry::trace::TraceContextExt;
 use opentelemetry::trace::Tracer;
@@ -14,15 +15,24 @@ use tracing::error;
 use tracing::info;
 
 use crate::alerts::outbound_webhook::alert_if_failure;
+use crate::errors::ExpectationsFailedError;
+use crate::errors::UnresolvedVariable;
+use crate::errors::UnresolvedVariablesError;
 use crate::otel::metrics::MonitorStatus;
 use crate::probe::model::StepResult;
+use crate::probe::variables::extract_values;
+use crate::probe::variables::substitute_alerts;
+use crate::probe::variables::substitute_auth;
 use crate::probe::variables::substitute_input_parameters;
 use crate::probe::variables::substitute_variables;
+use crate::probe::variables::StepSubstitution;
 use crate::probe::variables::StepVariables;
 use crate::probe::variables::StoryVariables;
 
+use super::auth::call_endpoint_with_auth;
 use super::expectations::validate_response;
-use super::http_probe::call_endpoint;
+use super::http_probe::ClientConfig;
+use super::http_probe::CookieJar;
 use super::model::Probe;
 use super::model::ProbeResult;
 use super::model::ProbeScheduleParameters;
@@ -42,6 +52,31 @@ fn time_since(timestamp: &chrono::DateTime<Utc>) -> u64 {
         .num_milliseconds() as u64
 }
 
+// Each failed expectation gets its own span event, alongside the error for the whole set
+fn record_expectation_failures(span: &SpanRef, err: &ExpectationsFailedError) {
+    span.record_error(err);
+    for failure in &err.failures {
+        span.add_event(
+            "expectation_failed",
+            vec![KeyValue::new("message", failure.to_string())],
+        );
+    }
+}
+
+// Recorded whether or not strict variables are enabled, as an empty value is a likely
+// cause of a failed request
+fn record_unresolved_variables(span: &SpanRef, unresolved: &[UnresolvedVariable]) {
+    for variable in unresolved {
+        span.add_event(
+            "unresolved_variable",
+            vec![
+                KeyValue::new("placeholder", variable.placeholder.clone()),
+                KeyValue::new("reason", variable.reason.clone()),
+            ],
+        );
+    }
+}
+
 // TODOs here: Step / Probe can be the same object
 // The timestamps are a little disorganised
 // Reduce nested code
@@ -60,7 +95,13 @@ impl Monitorable for Story {
         }))
         .collect::<Vec<_>>();
         app_state.metrics.runs.add(1, &story_attributes);
-        let mut story_variables = StoryVariables::new();
+        let mut story_variables =
+            StoryVariables::with_static_variables(&app_state.config.variables, &self.variables);
+        let strict_variables = self
+            .strict_variables
+            .unwrap_or(app_state.config.strict_variables);
+        // Only lives for this run, so sessions aren't shared between runs
+        let cookie_jar = self.cookie_jar.then(CookieJar::default);
         let mut step_results: Vec<StepResult> = vec![];
         let timestamp_started = Utc::now();
 
@@ -85,13 +126,56 @@ impl Monitorable for Story {
             let step_span = tracer.start_with_context(step.name.clone(), &root_cx);
             let step_cx = root_cx.with_span(step_span);
 
-            let url = substitute_variables(&step.url, &story_variables);
-            let input_parameters = substitute_input_parameters(&step.with, &story_variables);
+            let mut substitution = StepSubstitution::default();
+            let url = substitute_variables(&step.url, &story_variables, &mut substitution);
+            let input_parameters =
+                substitute_input_parameters(&step.with, &story_variables, &mut substitution);
+            let auth = substitute_auth(&step.auth, &story_variables, &mut substitution);
+            record_unresolved_variables(&step_cx.span(), &substitution.unresolved);
+            if strict_variables && !substitution.unresolved.is_empty() {
+                let e = UnresolvedVariablesError {
+                    variables: substitution.unresolved,
+                };
+                error!("Not calling endpoint for step {}: {}", step.name, e);
+                step_cx.span().set_status(Status::Error {
+                    description: "Unresolved variables".into(),
+                });
+                app_state.metrics.errors.add(1, &step_tags);
+                step_results.push(StepResult {
+                    step_name: step.name.clone(),
+                    success: false,
+                    error_message: Some(e.to_string()),
+                    timestamp_started: step_started,
+                    response: None,
+                    extracted: None,
+                    trace_id: None,
+                    span_id: None,
+                    peer_ip: None,
+                });
+                app_state
+                    .metrics
+                    .duration
+                    .record(time_since(&timestamp_started), &step_tags);
+                break;
+            }
 
-            let call_endpoint_result =
-                call_endpoint(&step.http_method, &url, &input_parameters, step.sensitive)
-                    .with_context(step_cx.clone())
-                    .await;
+            let call_endpoint_result = call_endpoint_with_auth(
+                &step.http_method,
+                &url,
+                &input_parameters,
+                step.sensitive,
+                ClientConfig {
+                    cookie_jar: cookie_jar.as_ref(),
+                    tls: step.tls.as_ref(),
+                    client: step.client.as_ref(),
+                    resolve: step.resolve.as_ref(),
+                    ip_family: step.ip_family,
+                },
+                &auth,
+                &app_state.tokens,
+            )
+            .with_context(step_cx.clone())
+            .await;
 
             match call_endpoint_result {
                 Ok(endpoint_result) => {
@@ -106,14 +190,14 @@ impl Monitorable for Story {
                         endpoint_result.status_code.to_string(),
                     ));
                     let expectations_result = validate_response(
-                        &step.name,
-                        endpoint_result.status_code,
-                        endpoint_result.body,
+                        &format!("{}/{}", self.name, step.name),
+                        &endpoint_result,
                         &step.expectations,
+                        &app_state.snapshots,
                     );
                     let mut monitor_status = MonitorStatus::Ok.as_u64();
                     if let Err(err) = expectations_result.as_ref() {
-                        span.record_error(&err);
+                        record_expectation_failures(&span, err);
                         span.set_status(Status::Error {
                             description: "Expectation failed".into(),
                         });
@@ -129,14 +213,25 @@ impl Monitorable for Story {
                         .status
                         .record(monitor_status, &story_attributes);
 
+                    let step_variables = StepVariables::from_endpoint_result(&endpoint_result);
+                    let extracted = step
+                        .extract
+                        .as_ref()
+                        .map(|extract| extract_values(extract, &endpoint_result))
+                        .unwrap_or_default();
                     let step_result = StepResult {
                         step_name: step.name.clone(),
                         timestamp_started: endpoint_result.timestamp_request_started,
                         success: expectations_result.is_ok(),
                         error_message: expectations_result.as_ref().err().map(|e| e.to_string()),
                         response: Some(probe_response),
+                        extracted: match step.sensitive || extracted.is_empty() {
+                            true => None,
+                            false => Some(extracted.clone()),
+                        },
                         trace_id: Some(endpoint_result.trace_id),
                         span_id: Some(endpoint_result.span_id),
+                        peer_ip: endpoint_result.peer_ip,
                     };
                     step_results.push(step_result);
 
@@ -148,12 +243,10 @@ impl Monitorable for Story {
                     // queries in promql don't miss the step from 0 -> 1
                     app_state.metrics.errors.add(0, &step_tags);
                     step_cx.span().set_status(Status::Ok);
-                    let step_variables = StepVariables {
-                        response_body: step_results.last().unwrap().response.clone().unwrap().body,
-                    };
                     story_variables
                         .steps
                         .insert(step.name.clone(), step_variables);
+                    story_variables.vars.extend(extracted);
                     app_state
                         .metrics
                         .duration
@@ -171,8 +264,10 @@ impl Monitorable for Story {
                         error_message: Some(e.to_string()),
                         timestamp_started: Utc::now(),
                         response: None,
+                        extracted: None,
                         trace_id: None,
                         span_id: None,
+                        peer_ip: None,
                     });
                     app_state
                         .metrics
@@ -205,7 +300,7 @@ impl Monitorable for Story {
             last_step.response.as_ref(),
             &self.name,
             timestamp_started,
-            &self.alerts,
+            &substitute_alerts(&self.alerts, &story_variables),
             &last_step.trace_id,
         )
         .await;
@@ -249,10 +344,40 @@ impl Monitorable for Probe {
         let root_span = global::tracer("probe_logic").start(self.name.clone());
 
         let root_cx = Context::default().with_span(root_span);
+
+        let variables =
+            StoryVariables::with_static_variables(&app_state.config.variables, &self.variables);
+        let mut substitution = StepSubstitution::default();
+        let url = substitute_variables(&self.url, &variables, &mut substitution);
+        let input_parameters =
+            substitute_input_parameters(&self.with, &variables, &mut substitution);
+        let auth = substitute_auth(&self.auth, &variables, &mut substitution);
+        record_unresolved_variables(&root_cx.span(), &substitution.unresolved);
+
         let call_endpoint_result =
-            call_endpoint(&self.http_method, &self.url, &self.with, self.sensitive)
+            if app_state.config.strict_variables && !substitution.unresolved.is_empty() {
+                Err(Box::new(UnresolvedVariablesError {
+                    variables: substitution.unresolved,
+                }) as Box<dyn std::error::Error + Send>)
+            } else {
+                call_endpoint_with_auth(
+                    &self.http_method,
+                    &url,
+                    &input_parameters,
+                    self.sensitive,
+                    ClientConfig {
+                        cookie_jar: None,
+                        tls: self.tls.as_ref(),
+                        client: self.client.as_ref(),
+                        resolve: self.resolve.as_ref(),
+                        ip_family: self.ip_family,
+                    },
+                    &auth,
+                    &app_state.tokens,
+                )
                 .with_context(root_cx.clone())
-                .await;
+                .await
+            };
 
         let probe_result = match call_endpoint_result {
             Ok(endpoint_result) => {
@@ -263,13 +388,13 @@ impl Monitorable for Probe {
                 let probe_response = endpoint_result.to_probe_response();
                 let expectations_result = validate_response(
                     &self.name,
-                    endpoint_result.status_code,
-                    endpoint_result.body,
+                    &endpoint_result,
                     &self.expectations,
+                    &app_state.snapshots,
                 );
 
                 if let Err(err) = expectations_result.as_ref() {
-                    root_cx.span().record_error(&err);
+                    record_expectation_failures(&root_cx.span(), err);
                 }
 
                 let mut monitor_status = MonitorStatus::Ok.as_u64();
@@ -288,6 +413,7 @@ impl Monitorable for Probe {
                     error_message: expectations_result.err().map(|e| e.to_string()),
                     response: Some(probe_response),
                     trace_id: Some(endpoint_result.trace_id),
+                    peer_ip: endpoint_result.peer_ip,
                 }
             }
             Err(e) => {
@@ -308,6 +434,7 @@ impl Monitorable for Probe {
                     error_message: Some(e.to_string()),
                     response: None,
                     trace_id: None,
+                    peer_ip: None,
                 }
             }
         };
@@ -339,7 +466,7 @@ impl Monitorable for Probe {
             probe_result.response.as_ref(),
             &self.name,
             timestamp,
-            &self.alerts,
+            &substitute_alerts(&self.alerts, &variables),
             &probe_result.trace_id,
         )
         .await;
@@ -364,12 +491,13 @@ mod probe_logic_tests {
 
     use std::collections::HashMap;
     use std::sync::Arc;
+    use std::time::Duration;
 
     use crate::app_state::AppState;
     use crate::config::Config;
     use crate::probe::model::{
-        ExpectField, ExpectOperation, ProbeAlert, ProbeExpectation, ProbeInputParameters,
-        ProbeScheduleParameters, Step, Story,
+        ExpectField, ExpectOperation, Expectation, Probe, ProbeAlert, ProbeExpectation,
+        ProbeInputParameters, ProbeScheduleParameters, Step, Story,
     };
     use crate::probe::probe_logic::Monitorable;
     use wiremock::matchers::{header, method, path};
@@ -384,6 +512,8 @@ mod probe_logic_tests {
         let app_state = Arc::new(AppState::new(Config {
             probes: vec![],
             stories: vec![],
+            strict_variables: false,
+            variables: HashMap::new(),
         }));
 
         Mock::given(method("GET"))
@@ -410,6 +540,12 @@ mod probe_logic_tests {
                     http_method: "GET".to_owned(),
                     expectations: None,
                     sensitive: false,
+                    auth: None,
+                    tls: None,
+                    client: None,
+                    resolve: None,
+                    ip_family: None,
+                    extract: None,
                 },
                 Step {
                     name: "Step 2".to_owned(),
@@ -418,6 +554,12 @@ mod probe_logic_tests {
                     http_method: "GET".to_owned(),
                     expectations: None,
                     sensitive: false,
+                    auth: None,
+                    tls: None,
+                    client: None,
+                    resolve: None,
+                    ip_family: None,
+                    extract: None,
                 },
             ],
             schedule: ProbeScheduleParameters {
@@ -426,6 +568,9 @@ mod probe_logic_tests {
             },
             tags: None,
             alerts: None,
+            strict_variables: None,
+            cookie_jar: false,
+            variables: None,
         };
 
         story.probe_and_store_result(app_state.clone()).await;
@@ -448,6 +593,8 @@ mod probe_logic_tests {
         let app_state = Arc::new(AppState::new(Config {
             probes: vec![],
             stories: vec![],
+            strict_variables: false,
+            variables: HashMap::new(),
         }));
 
         Mock::given(method("GET"))
@@ -474,18 +621,33 @@ mod probe_logic_tests {
                     http_method: "GET".to_owned(),
                     expectations: None,
                     sensitive: false,
+                    auth: None,
+                    tls: None,
+                    client: None,
+                    resolve: None,
+                    ip_family: None,
+                    extract: None,
                 },
                 Step {
                     name: "Step 2".to_owned(),
                     url: format!("{}{}", mock_server.uri(), step2_path.to_owned()),
                     with: None,
                     http_method: "GET".to_owned(),
-                    expectations: Some(vec![ProbeExpectation {
+                    expectations: Some(vec![Expectation::Field(ProbeExpectation {
                         field: ExpectField::StatusCode,
                         operation: ExpectOperation::Equals,
                         value: "200".to_owned(),
-                    }]),
+                        schema: None,
+                        ignore_paths: vec![],
+                        compiled: Default::default(),
+                    })]),
                     sensitive: false,
+                    auth: None,
+                    tls: None,
+                    client: None,
+                    resolve: None,
+                    ip_family: None,
+                    extract: None,
                 },
             ],
             schedule: ProbeScheduleParameters {
@@ -496,6 +658,9 @@ mod probe_logic_tests {
                 url: format!("{}{}", mock_server.uri(), alert_path.to_owned()),
             }]),
             tags: None,
+            strict_variables: None,
+            cookie_jar: false,
+            variables: None,
         };
 
         story.probe_and_store_result(app_state.clone()).await;
@@ -508,6 +673,372 @@ mod probe_logic_tests {
         assert_eq!(2, story_result.step_results.len());
     }
 
+    #[tokio::test]
+    async fn test_story_step_fails_when_too_slow() {
+        let mock_server = MockServer::start().await;
+        let step1_path = "/slow";
+        let story_name = "Slow Flow";
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![],
+            stories: vec![],
+            strict_variables: false,
+            variables: HashMap::new(),
+        }));
+
+        Mock::given(method("GET"))
+            .and(path(step1_path))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(600)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let story = Story {
+            name: story_name.to_owned(),
+            steps: vec![Step {
+                name: "Step 1".to_owned(),
+                url: format!("{}{}", mock_server.uri(), step1_path.to_owned()),
+                with: None,
+                http_method: "GET".to_owned(),
+                expectations: Some(vec![Expectation::Field(ProbeExpectation {
+                    field: ExpectField::ResponseTimeMs,
+                    operation: ExpectOperation::LessThan,
+                    value: "200".to_owned(),
+                    schema: None,
+                    ignore_paths: vec![],
+                    compiled: Default::default(),
+                })]),
+                sensitive: false,
+                auth: None,
+                tls: None,
+                client: None,
+                resolve: None,
+                ip_family: None,
+                extract: None,
+            }],
+            schedule: ProbeScheduleParameters {
+                initial_delay: 0,
+                interval: 0,
+            },
+            alerts: None,
+            tags: None,
+            strict_variables: None,
+            cookie_jar: false,
+            variables: None,
+        };
+
+        story.probe_and_store_result(app_state.clone()).await;
+
+        let story_result_map = app_state.story_results.read().unwrap();
+        let story_result = &story_result_map[story_name][0];
+        assert!(!story_result.success);
+        assert!(!story_result.step_results[0].success);
+    }
+
+    #[tokio::test]
+    async fn test_story_strict_variables_fails_before_request() {
+        let mock_server = MockServer::start().await;
+        let step1_path = "/test1";
+
+        Mock::given(method("GET"))
+            .and(path(step1_path))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let story_name = "Strict Story";
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![],
+            stories: vec![],
+            strict_variables: false,
+            variables: HashMap::new(),
+        }));
+        let story = Story {
+            name: story_name.to_owned(),
+            steps: vec![Step {
+                name: "Step 1".to_owned(),
+                url: format!("{}{}", mock_server.uri(), step1_path),
+                with: Some(ProbeInputParameters {
+                    body: None,
+                    headers: Some(HashMap::from([(
+                        "Authorization".to_owned(),
+                        "Bearer ${{steps.get-token.response.body.token}}".to_owned(),
+                    )])),
+                    form: None,
+                    multipart: None,
+                    body_file: None,
+                    body_file_contents: None,
+                    query: None,
+                    timeout_seconds: None,
+                }),
+                http_method: "GET".to_owned(),
+                expectations: None,
+                sensitive: false,
+                auth: None,
+                tls: None,
+                client: None,
+                resolve: None,
+                ip_family: None,
+                extract: None,
+            }],
+            schedule: ProbeScheduleParameters {
+                initial_delay: 0,
+                interval: 0,
+            },
+            alerts: None,
+            tags: None,
+            strict_variables: Some(true),
+            cookie_jar: false,
+            variables: None,
+        };
+
+        story.probe_and_store_result(app_state.clone()).await;
+
+        let story_result_map = app_state.story_results.read().unwrap();
+        let step_result = &story_result_map[story_name][0].step_results[0];
+        assert!(!step_result.success);
+        assert!(step_result.response.is_none());
+        assert!(step_result
+            .error_message
+            .as_ref()
+            .unwrap()
+            .contains("'${{steps.get-token.response.body.token}}'"));
+    }
+
+    #[tokio::test]
+    async fn test_story_extracted_values_used_in_later_steps() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"data": {"token": "abc123"}}"#),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/profile"))
+            .and(header("Authorization", "Bearer abc123"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let story_name = "Extract Story";
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![],
+            stories: vec![],
+            strict_variables: false,
+            variables: HashMap::new(),
+        }));
+        let yaml = format!(
+            r#"
+            name: {}
+            schedule:
+              initial_delay: 0
+              interval: 0
+            steps:
+              - name: login
+                url: {}/login
+                http_method: POST
+                extract:
+                  token:
+                    JsonPath: $.data.token
+              - name: profile
+                url: {}/profile
+                http_method: GET
+                with:
+                  headers:
+                    Authorization: Bearer ${{{{vars.token}}}}
+                expectations:
+                  - field: StatusCode
+                    operation: Equals
+                    value: "200"
+            "#,
+            story_name,
+            mock_server.uri(),
+            mock_server.uri()
+        );
+        let story: Story = serde_yaml::from_str(&yaml).unwrap();
+
+        story.probe_and_store_result(app_state.clone()).await;
+
+        let story_result_map = app_state.story_results.read().unwrap();
+        let story_result = &story_result_map[story_name][0];
+        assert!(story_result.success);
+        assert_eq!(
+            "abc123",
+            story_result.step_results[0].extracted.as_ref().unwrap()["token"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_story_sensitive_body_not_in_later_step_error() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("token=secret-abc123"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let story_name = "Sensitive Story";
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![],
+            stories: vec![],
+            strict_variables: true,
+            variables: HashMap::new(),
+        }));
+        let yaml = format!(
+            r#"
+            name: {}
+            schedule:
+              initial_delay: 0
+              interval: 0
+            steps:
+              - name: login
+                url: {}/login
+                http_method: POST
+                sensitive: true
+              - name: profile
+                url: {}/profile
+                http_method: GET
+                with:
+                  headers:
+                    Authorization: Bearer ${{{{steps.login.response.body.token}}}}
+            "#,
+            story_name,
+            mock_server.uri(),
+            mock_server.uri()
+        );
+        let story: Story = serde_yaml::from_str(&yaml).unwrap();
+
+        story.probe_and_store_result(app_state.clone()).await;
+
+        let story_result_map = app_state.story_results.read().unwrap();
+        let error_message = story_result_map[story_name][0].step_results[1]
+            .error_message
+            .clone()
+            .unwrap();
+        assert!(error_message.contains("not valid JSON"));
+        assert!(!error_message.contains("secret-abc123"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_substitutes_static_variables() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/v2/tenants/probe-tenant"))
+            .and(header("X-Api-Version", "2"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![],
+            stories: vec![],
+            strict_variables: false,
+            variables: HashMap::from([
+                ("base_url".to_owned(), mock_server.uri()),
+                ("api_version".to_owned(), "2".to_owned()),
+                ("tenant".to_owned(), "global-tenant".to_owned()),
+            ]),
+        }));
+        let probe: Probe = serde_yaml::from_str(
+            r#"
+            name: Static Variables Probe
+            url: ${{ vars.base_url }}/v${{ vars.api_version }}/tenants/${{ vars.tenant }}
+            http_method: GET
+            with:
+              headers:
+                X-Api-Version: ${{ vars.api_version }}
+            variables:
+              tenant: probe-tenant
+            expectations:
+              - field: StatusCode
+                operation: Equals
+                value: "200"
+            schedule:
+              initial_delay: 0
+              interval: 0
+            "#,
+        )
+        .unwrap();
+
+        probe.probe_and_store_result(app_state.clone()).await;
+
+        let probe_results = app_state.probe_results.read().unwrap();
+        assert!(probe_results["Static Variables Probe"][0].success);
+    }
+
+    #[tokio::test]
+    async fn test_story_cookie_jar_keeps_session_between_steps() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .respond_with(
+                ResponseTemplate::new(200).insert_header("Set-Cookie", "session=abc123; Path=/"),
+            )
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/profile"))
+            .and(header("Cookie", "session=abc123"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/profile"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let app_state = Arc::new(AppState::new(Config {
+            probes: vec![],
+            stories: vec![],
+            strict_variables: false,
+            variables: HashMap::new(),
+        }));
+        for (story_name, cookie_jar) in [("With Cookies", true), ("Without Cookies", false)] {
+            let yaml = format!(
+                r#"
+                name: {}
+                cookie_jar: {}
+                schedule:
+                  initial_delay: 0
+                  interval: 0
+                steps:
+                  - name: login
+                    url: {}/login
+                    http_method: POST
+                  - name: profile
+                    url: {}/profile
+                    http_method: GET
+                    expectations:
+                      - field: StatusCode
+                        operation: Equals
+                        value: "200"
+                "#,
+                story_name,
+                cookie_jar,
+                mock_server.uri(),
+                mock_server.uri()
+            );
+            let story: Story = serde_yaml::from_str(&yaml).unwrap();
+            story.probe_and_store_result(app_state.clone()).await;
+        }
+
+        let story_result_map = app_state.story_results.read().unwrap();
+        assert!(story_result_map["With Cookies"][0].success);
+        assert!(!story_result_map["Without Cookies"][0].success);
+    }
+
     #[tokio::test]
     async fn test_story_passes_all_variables() {
         let mock_server = MockServer::start().await;
@@ -529,6 +1060,8 @@ mod probe_logic_tests {
         let app_state = Arc::new(AppState::new(Config {
             probes: vec![],
             stories: vec![],
+            strict_variables: false,
+            variables: HashMap::new(),
         }));
 
         Mock::given(method("GET"))
@@ -556,6 +1089,12 @@ mod probe_logic_tests {
                     http_method: "GET".to_owned(),
                     expectations: None,
                     sensitive: false,
+                    auth: None,
+                    tls: None,
+                    client: None,
+                    resolve: None,
+                    ip_family: None,
+                    extract: None,
                 },
                 Step {
                     name: "Step 2".to_owned(),
@@ -563,15 +1102,29 @@ mod probe_logic_tests {
                     with: Some(ProbeInputParameters {
                         headers: Some(step2_headers),
                         body: Some(step2_body_str.to_owned()),
+                        form: None,
+                        multipart: None,
+                        body_file: None,
+                        body_file_contents: None,
+                        query: None,
                         timeout_seconds: None,
                     }),
                     http_method: "POST".to_owned(),
-                    expectations: Some(vec![ProbeExpectation {
+                    expectations: Some(vec![Expectation::Field(ProbeExpectation {
                         field: ExpectField::StatusCode,
                         operation: ExpectOperation::Equals,
                         value: "200".to_owned(),
-                    }]),
+                        schema: None,
+                        ignore_paths: vec![],
+                        compiled: Default::default(),
+                    })]),
                     sensitive: false,
+                    auth: None,
+                    tls: None,
+                    client: None,
+                    resolve: None,
+                    ip_family: None,
+                    extract: None,
                 },
             ],
             schedule: ProbeScheduleParameters {
@@ -580,6 +1133,9 @@ mod probe_logic_tests {
             },
             alerts: None,
             tags: None,
+            strict_variables: None,
+            cookie_jar: false,
+            variables: None,
         };
 
         story.probe_and_store_result(app_state.clone()).await;
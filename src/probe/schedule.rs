@@ -52,6 +52,7 @@ pub async fn probing_loop<T: Monitorable>(monitorable: &T, app_state: Arc<AppSta
 
 #[cfg(test)]
 mod schedule_tests {
+    use std::collections::HashMap;
 
     use crate::config::Config;
     use crate::probe::schedule::schedule_probes;
@@ -99,6 +100,8 @@ mod schedule_tests {
         let config = Config {
             probes: vec![probe],
             stories: vec![],
+            strict_variables: false,
+            variables: HashMap::new(),
         };
 
         let app_state = Arc::new(AppState::new(config));
@@ -134,6 +137,8 @@ mod schedule_tests {
         let config = Config {
             probes: vec![probe],
             stories: vec![],
+            strict_variables: false,
+            variables: HashMap::new(),
         };
 
         let app_state = Arc::new(AppState::new(config));
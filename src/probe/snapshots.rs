@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use serde_json::{Map, Value};
+use serde_json_path::JsonPath;
+
+// Stores the structure of the first successful response for each MatchesSnapshot
+// expectation, keyed by the probe or step and the position of the expectation.
+pub struct Snapshots {
+    shapes: RwLock<HashMap<String, Value>>,
+}
+
+impl Snapshots {
+    pub fn new() -> Snapshots {
+        Snapshots {
+            shapes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.shapes.read().unwrap().get(key).cloned()
+    }
+
+    pub fn record(&self, key: String, shape: Value) {
+        self.shapes.write().unwrap().entry(key).or_insert(shape);
+    }
+}
+
+// Reduces a JSON value to its structure: objects keep their keys, arrays keep the
+// structure of their first element, and other values are replaced by their type name.
+pub fn json_shape(value: &Value, ignore_paths: &[String]) -> Result<Value, String> {
+    let mut value = value.clone();
+    remove_ignored_paths(&mut value, ignore_paths)?;
+    Ok(shape_of(&value))
+}
+
+fn shape_of(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), shape_of(value)))
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().take(1).map(shape_of).collect()),
+        other => Value::String(type_name(other).to_owned()),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// Scalar types are stored as their type name in shapes
+fn shape_type(shape: &Value) -> &str {
+    match shape {
+        Value::String(name) => name,
+        other => type_name(other),
+    }
+}
+
+fn remove_ignored_paths(value: &mut Value, ignore_paths: &[String]) -> Result<(), String> {
+    let mut pointers = vec![];
+    for path in ignore_paths {
+        let json_path = JsonPath::parse(path)
+            .map_err(|e| format!("Invalid ignored JSONPath '{}': {}.", path, e))?;
+        pointers.extend(
+            json_path
+                .query_located(value)
+                .locations()
+                .map(|location| location.to_json_pointer()),
+        );
+    }
+    // Remove the deepest and last elements first, so earlier array indexes stay valid
+    pointers.sort_by_cached_key(|pointer| pointer_segments(pointer));
+    pointers.dedup();
+    for pointer in pointers.iter().rev() {
+        let Some((parent, last)) = pointer.rsplit_once('/') else {
+            continue;
+        };
+        let last = unescape_segment(last);
+        match value.pointer_mut(parent) {
+            Some(Value::Object(map)) => {
+                map.remove(&last);
+            }
+            Some(Value::Array(items)) => {
+                if let Ok(index) = last.parse::<usize>() {
+                    if index < items.len() {
+                        items.remove(index);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Array indexes are compared as numbers, so `/items/10` comes after `/items/2`
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum PointerSegment {
+    Index(usize),
+    Key(String),
+}
+
+fn pointer_segments(pointer: &str) -> Vec<PointerSegment> {
+    pointer
+        .split('/')
+        .skip(1)
+        .map(|segment| match segment.parse::<usize>() {
+            Ok(index) => PointerSegment::Index(index),
+            Err(_) => PointerSegment::Key(unescape_segment(segment)),
+        })
+        .collect()
+}
+
+fn unescape_segment(segment: &str) -> String {
+    segment.replace("~1", "/").replace("~0", "~")
+}
+
+// Lists the differences in structure between a recorded shape and a new one
+pub fn diff_shapes(expected: &Value, received: &Value) -> Vec<String> {
+    let mut differences = vec![];
+    diff_shapes_at("", expected, received, &mut differences);
+    differences
+}
+
+fn diff_shapes_at(path: &str, expected: &Value, received: &Value, differences: &mut Vec<String>) {
+    match (expected, received) {
+        (Value::Object(expected), Value::Object(received)) => {
+            for (key, expected_value) in expected {
+                let key_path = format!("{}/{}", path, key);
+                match received.get(key) {
+                    Some(received_value) => {
+                        diff_shapes_at(&key_path, expected_value, received_value, differences)
+                    }
+                    None => differences.push(format!("{}: key removed", key_path)),
+                }
+            }
+            for key in received.keys().filter(|key| !expected.contains_key(*key)) {
+                differences.push(format!("{}/{}: key added", path, key));
+            }
+        }
+        // Empty arrays don't tell us anything about the structure of their items
+        (Value::Array(expected), Value::Array(received)) => {
+            if let (Some(expected_item), Some(received_item)) = (expected.first(), received.first())
+            {
+                diff_shapes_at(
+                    &format!("{}/*", path),
+                    expected_item,
+                    received_item,
+                    differences,
+                );
+            }
+        }
+        (expected, received) => {
+            let (expected_type, received_type) = (shape_type(expected), shape_type(received));
+            if expected_type != received_type {
+                let path = if path.is_empty() { "/" } else { path };
+                differences.push(format!(
+                    "{}: type changed from {} to {}",
+                    path, expected_type, received_type
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_json_shape_replaces_values_with_types() {
+        let value = json!({"name": "a", "count": 2, "items": [{"id": 1}, {"id": 2}], "next": null});
+
+        let shape = json_shape(&value, &[]).unwrap();
+
+        assert_eq!(
+            json!({"name": "string", "count": "number", "items": [{"id": "number"}], "next": "null"}),
+            shape
+        );
+    }
+
+    #[test]
+    fn test_json_shape_removes_ignored_paths() {
+        let value =
+            json!({"id": "abc", "updated_at": 1700000000, "items": [{"id": 1, "name": "x"}]});
+        let ignore_paths = vec!["$.updated_at".to_owned(), "$.items[*].id".to_owned()];
+
+        let shape = json_shape(&value, &ignore_paths).unwrap();
+
+        assert_eq!(
+            json!({"id": "string", "items": [{"name": "string"}]}),
+            shape
+        );
+    }
+
+    #[test]
+    fn test_ignored_paths_in_long_arrays() {
+        let mut value = json!({"items": (0..12).collect::<Vec<_>>()});
+        let ignore_paths = vec!["$.items[2]".to_owned(), "$.items[10]".to_owned()];
+
+        remove_ignored_paths(&mut value, &ignore_paths).unwrap();
+
+        assert_eq!(json!({"items": [0, 1, 3, 4, 5, 6, 7, 8, 9, 11]}), value);
+    }
+
+    #[test]
+    fn test_diff_shapes_lists_changes() {
+        let expected = json!({"name": "string", "count": "number", "items": [{"id": "number"}]});
+        let received = json!({"name": "string", "count": "string", "items": [{"key": "number"}], "extra": "boolean"});
+
+        let differences = diff_shapes(&expected, &received);
+
+        assert_eq!(
+            vec![
+                "/count: type changed from number to string",
+                "/items/*/id: key removed",
+                "/items/*/key: key added",
+                "/extra: key added",
+            ],
+            differences
+        );
+    }
+
+    #[test]
+    fn test_diff_shapes_ignores_values_and_empty_arrays() {
+        let expected = json_shape(&json!({"a": 1, "b": [{"c": true}]}), &[]).unwrap();
+        let received = json_shape(&json!({"a": 5, "b": []}), &[]).unwrap();
+
+        assert!(diff_shapes(&expected, &received).is_empty());
+    }
+}
@@ -1,26 +1,80 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde_json::Value;
+use serde_json_path::JsonPath;
 use std::collections::HashMap;
 use tracing::error;
-use uuid::Uuid;
 
+use crate::errors::InvalidExtractError;
+use crate::errors::UnresolvedVariable;
+
+use super::expectations::resolve_json_path;
+use super::functions::apply_function;
+use super::generators::generate_value;
+use super::markup::css_selector_value;
+use super::markup::xpath_value;
+use super::model::Auth;
+use super::model::EndpointResult;
+use super::model::ExtractSource;
+use super::model::MultipartContent;
+use super::model::MultipartFile;
+use super::model::MultipartPart;
+use super::model::OAuth2ClientCredentials;
+use super::model::ProbeAlert;
 use super::model::ProbeInputParameters;
 
 pub struct StoryVariables {
     pub steps: HashMap<String, StepVariables>,
+    pub vars: HashMap<String, String>,
 }
 
 impl StoryVariables {
     pub fn new() -> StoryVariables {
         StoryVariables {
             steps: HashMap::new(),
+            vars: HashMap::new(),
         }
     }
+
+    // Variables set on a probe or story override global ones with the same name
+    pub fn with_static_variables(
+        global: &HashMap<String, String>,
+        local: &Option<HashMap<String, String>>,
+    ) -> StoryVariables {
+        let mut variables = StoryVariables::new();
+        variables.vars = global.clone();
+        variables
+            .vars
+            .extend(local.iter().flatten().map(|(k, v)| (k.clone(), v.clone())));
+        variables
+    }
 }
 
+#[derive(Default)]
 pub struct StepVariables {
     pub response_body: String,
+    pub response_headers: HashMap<String, String>,
+    pub status_code: u32,
+    pub duration_ms: i64,
+}
+
+impl StepVariables {
+    pub fn from_endpoint_result(endpoint_result: &EndpointResult) -> StepVariables {
+        StepVariables {
+            response_body: endpoint_result.body.clone(),
+            response_headers: endpoint_result.headers.clone(),
+            status_code: endpoint_result.status_code,
+            duration_ms: endpoint_result.response_time_ms(),
+        }
+    }
+}
+
+// State kept while substituting the variables of a single step
+#[derive(Default)]
+pub struct StepSubstitution {
+    pub unresolved: Vec<UnresolvedVariable>,
+    generated: HashMap<String, String>,
+    request_body: Option<String>,
 }
 
 lazy_static! {
@@ -30,98 +84,444 @@ lazy_static! {
 pub fn substitute_input_parameters(
     input_parameters: &Option<ProbeInputParameters>,
     variables: &StoryVariables,
+    substitution: &mut StepSubstitution,
 ) -> Option<ProbeInputParameters> {
-    input_parameters.as_ref().map(|input| ProbeInputParameters {
-        body: input
+    input_parameters.as_ref().map(|input| {
+        // The body is substituted first, so that headers can refer to it as `request.body`
+        let body = input
             .body
             .as_ref()
-            .map(|body| substitute_variables(body, variables)),
-        headers: input
-            .headers
+            .map(|body| substitute_variables(body, variables, substitution));
+        substitution.request_body = body.clone();
+        let body_file = input
+            .body_file
+            .as_ref()
+            .map(|path| substitute_variables(path, variables, substitution));
+        // Text files are substituted like a body, while binary files are sent as they are.
+        // A file that can't be read is reported when the request is built.
+        let body_file_contents = body_file
             .as_ref()
-            .map(|headers| substitute_variables_in_headers(headers, variables)),
-        timeout_seconds: input.timeout_seconds,
+            .and_then(|path| std::fs::read(path).ok())
+            .map(|contents| match String::from_utf8(contents) {
+                Ok(text) => {
+                    let text = substitute_variables(&text, variables, substitution);
+                    substitution.request_body = Some(text.clone());
+                    text.into_bytes()
+                }
+                Err(e) => e.into_bytes(),
+            });
+        ProbeInputParameters {
+            body,
+            headers: input
+                .headers
+                .as_ref()
+                .map(|headers| substitute_variables_in_map(headers, variables, substitution)),
+            form: input
+                .form
+                .as_ref()
+                .map(|form| substitute_variables_in_map(form, variables, substitution)),
+            multipart: input.multipart.as_ref().map(|multipart| {
+                multipart
+                    .iter()
+                    .map(|part| substitute_multipart_part(part, variables, substitution))
+                    .collect()
+            }),
+            body_file,
+            body_file_contents,
+            query: input
+                .query
+                .as_ref()
+                .map(|query| substitute_variables_in_map(query, variables, substitution)),
+            timeout_seconds: input.timeout_seconds,
+        }
     })
 }
 
-pub fn substitute_variables_in_headers(
-    headers: &HashMap<String, String>,
+pub fn substitute_auth(
+    auth: &Option<Auth>,
     variables: &StoryVariables,
+    substitution: &mut StepSubstitution,
+) -> Option<Auth> {
+    let mut substitute = |value: &String| substitute_variables(value, variables, substitution);
+    auth.as_ref().map(|auth| match auth {
+        Auth::Basic { username, password } => Auth::Basic {
+            username: substitute(username),
+            password: substitute(password),
+        },
+        Auth::Bearer { token } => Auth::Bearer {
+            token: substitute(token),
+        },
+        Auth::Oauth2ClientCredentials(credentials) => {
+            Auth::Oauth2ClientCredentials(OAuth2ClientCredentials {
+                token_url: substitute(&credentials.token_url),
+                client_id: substitute(&credentials.client_id),
+                client_secret: substitute(&credentials.client_secret),
+                scopes: credentials.scopes.iter().map(&mut substitute).collect(),
+            })
+        }
+    })
+}
+
+// Alert urls are substituted when an alert is sent, so in runtime mode they can use
+// environment variables, such as a webhook token
+pub fn substitute_alerts(
+    alerts: &Option<Vec<ProbeAlert>>,
+    variables: &StoryVariables,
+) -> Option<Vec<ProbeAlert>> {
+    alerts.as_ref().map(|alerts| {
+        alerts
+            .iter()
+            .map(|alert| ProbeAlert {
+                url: substitute_variables(&alert.url, variables, &mut StepSubstitution::default()),
+            })
+            .collect()
+    })
+}
+
+pub fn substitute_variables_in_map(
+    map: &HashMap<String, String>,
+    variables: &StoryVariables,
+    substitution: &mut StepSubstitution,
 ) -> HashMap<String, String> {
-    headers
-        .iter()
+    map.iter()
         .map(|(key, value)| {
-            let substituted_key = substitute_variables(key, variables);
-            let substituted_value = substitute_variables(value, variables);
+            let substituted_key = substitute_variables(key, variables, substitution);
+            let substituted_value = substitute_variables(value, variables, substitution);
             (substituted_key, substituted_value)
         })
         .collect()
 }
 
-// This could return an error in future - for now it fills an empty string
-pub fn substitute_variables(content: &str, variables: &StoryVariables) -> String {
+fn substitute_multipart_part(
+    part: &MultipartPart,
+    variables: &StoryVariables,
+    substitution: &mut StepSubstitution,
+) -> MultipartPart {
+    let content = match &part.content {
+        MultipartContent::Text { value } => MultipartContent::Text {
+            value: substitute_variables(value, variables, substitution),
+        },
+        MultipartContent::File(file) => MultipartContent::File(MultipartFile {
+            file: substitute_variables(&file.file, variables, substitution),
+            filename: file
+                .filename
+                .as_ref()
+                .map(|filename| substitute_variables(filename, variables, substitution)),
+            content_type: file.content_type.clone(),
+        }),
+    };
+    MultipartPart {
+        name: substitute_variables(&part.name, variables, substitution),
+        content,
+    }
+}
+
+// Variables which can't be resolved are filled with an empty string, and recorded
+// in the substitution so callers can report or fail on them
+pub fn substitute_variables(
+    content: &str,
+    variables: &StoryVariables,
+    substitution: &mut StepSubstitution,
+) -> String {
     SUB_REGEX
         .replace_all(content, |caps: &regex::Captures| {
-            let placeholder = &caps[1].trim();
-            let parts: Vec<&str> = placeholder.split('.').collect();
-
-            match parts[0] {
-                "steps" => substitute_step_value(&parts[1..], variables),
-                "generate" => get_generated_value(parts.get(1)),
-                _ => "".to_string(),
-            }
+            evaluate_expression(caps[1].trim(), variables, substitution).unwrap_or_else(|reason| {
+                error!(
+                    "Error: Could not resolve variable '{}': {}",
+                    &caps[0], reason
+                );
+                substitution.unresolved.push(UnresolvedVariable {
+                    placeholder: caps[0].to_owned(),
+                    reason,
+                });
+                "".to_string()
+            })
         })
         .to_string()
 }
 
-fn get_generated_value(type_to_generate: Option<&&str>) -> String {
-    match type_to_generate {
-        Some(&"uuid") => Uuid::new_v4().to_string(),
-        _ => "".to_string(),
+// Expressions are a value, optionally piped through functions, e.g. `vars.user | base64`.
+// Values are variables, quoted strings, or function calls such as `sha256(request.body)`.
+fn evaluate_expression(
+    expression: &str,
+    variables: &StoryVariables,
+    substitution: &mut StepSubstitution,
+) -> Result<String, String> {
+    let mut stages = split_outside_brackets(expression, '|').into_iter();
+    let first = stages.next().unwrap_or_default().trim();
+    let mut value = evaluate_value(first, variables, substitution)?;
+    for function in stages {
+        value = apply_function(function.trim(), &[value])?;
+    }
+    Ok(value)
+}
+
+fn evaluate_value(
+    value: &str,
+    variables: &StoryVariables,
+    substitution: &mut StepSubstitution,
+) -> Result<String, String> {
+    if let Some(literal) = quoted_string(value) {
+        return Ok(literal.to_owned());
+    }
+    if let Some((name, args)) = function_call(value) {
+        let args = split_outside_brackets(args, ',')
+            .into_iter()
+            .map(|arg| evaluate_expression(arg.trim(), variables, substitution))
+            .collect::<Result<Vec<_>, _>>()?;
+        return apply_function(name, &args);
+    }
+
+    // File paths can contain dots, so aren't split like other variables
+    if let Some(path) = value.strip_prefix("file.") {
+        return std::fs::read_to_string(path)
+            .map(|content| content.trim_end_matches(['\n', '\r']).to_owned())
+            .map_err(|e| format!("Could not read file '{}': {}", path, e));
+    }
+
+    let parts: Vec<&str> = value.split('.').collect();
+    match parts[0] {
+        "env" => {
+            let name = parts[1..].join(".");
+            std::env::var(&name).map_err(|_| format!("Environment variable '{}' is not set.", name))
+        }
+        "steps" => substitute_step_value(&parts[1..], variables),
+        "generate" => get_generated_value(&parts[1..].join("."), substitution),
+        "vars" => get_named_value(&parts[1..], variables),
+        "request" => match (&parts[1..], &substitution.request_body) {
+            (["body"], Some(body)) => Ok(body.clone()),
+            (["body"], None) => Err("The request has no body.".to_owned()),
+            _ => Err(format!("Unknown request value '{}'.", parts[1..].join("."))),
+        },
+        other => Err(format!("Unknown variable type '{}'.", other)),
+    }
+}
+
+fn quoted_string(value: &str) -> Option<&str> {
+    ['\'', '"'].into_iter().find_map(|quote| {
+        value
+            .strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+    })
+}
+
+// Function calls are a plain name followed by arguments, e.g. `hmac_sha256(vars.key, 'data')`.
+// Names containing dots, like `generate.int(1,100)`, are variables rather than functions.
+fn function_call(value: &str) -> Option<(&str, &str)> {
+    let (name, rest) = value.split_once('(')?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
     }
+    Some((name, rest.strip_suffix(')')?))
 }
 
-fn substitute_step_value(parts: &[&str], variables: &StoryVariables) -> String {
+fn get_named_value(parts: &[&str], variables: &StoryVariables) -> Result<String, String> {
+    let name = parts.join(".");
+    variables
+        .vars
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| format!("Variable '{}' not found.", name))
+}
+
+// The same generator gives the same value everywhere in a step, e.g. in both the url and body
+fn get_generated_value(
+    expression: &str,
+    substitution: &mut StepSubstitution,
+) -> Result<String, String> {
+    if let Some(value) = substitution.generated.get(expression) {
+        return Ok(value.clone());
+    }
+    let value = generate_value(expression)?;
+    substitution
+        .generated
+        .insert(expression.to_owned(), value.clone());
+    Ok(value)
+}
+
+// Step values are written as `steps.<name>.response.<value>`, where the value is
+// `body` (optionally followed by a path), `headers.<name>`, `status_code` or `duration_ms`
+fn substitute_step_value(parts: &[&str], variables: &StoryVariables) -> Result<String, String> {
     let step_name = parts[0];
 
-    match variables.steps.get(step_name) {
-        Some(step) => {
-            // TODO: We should check .response and .body
-            if parts.len() > 3 {
-                get_nested_json_value(&parts[3..], &step.response_body)
+    let Some(step) = variables.steps.get(step_name) else {
+        return Err(format!("Step name '{}' not found.", step_name));
+    };
+    if parts.get(1) != Some(&"response") {
+        return Err(format!(
+            "Expected 'response' after step name '{}', found '{}'.",
+            step_name,
+            parts[1..].join(".")
+        ));
+    }
+
+    // Rejoined, as XPath expressions, CSS selectors, filters and header names can contain dots
+    let path = parts
+        .get(3..)
+        .map(|rest| rest.join("."))
+        .unwrap_or_default();
+    match (parts.get(2), path.as_str()) {
+        (Some(&"body"), "") => Ok(step.response_body.clone()),
+        (Some(&"body"), path) => {
+            if let Some(expression) = function_argument(path, "xpath") {
+                xpath_value(&step.response_body, expression)
+            } else if let Some(selector) = function_argument(path, "css") {
+                css_selector_value(&step.response_body, selector)
             } else {
-                step.response_body.clone()
+                get_nested_json_value(path, &step.response_body)
             }
         }
-        None => {
-            error!("Error: Step name '{}' not found.", step_name);
-            "".to_string()
+        (Some(&"headers"), name) if !name.is_empty() => step
+            .response_headers
+            .get(&name.to_lowercase())
+            .cloned()
+            .ok_or_else(|| {
+                format!(
+                    "Header '{}' not found in response of step '{}'.",
+                    name, step_name
+                )
+            }),
+        (Some(&"status_code"), "") => Ok(step.status_code.to_string()),
+        (Some(&"duration_ms"), "") => Ok(step.duration_ms.to_string()),
+        _ => Err(format!(
+            "Unknown response value '{}' for step '{}'.",
+            parts[2..].join("."),
+            step_name
+        )),
+    }
+}
+
+// Values which can't be extracted are left out, so that using them later is reported
+// as an unresolved variable
+pub fn extract_values(
+    extract: &HashMap<String, ExtractSource>,
+    endpoint_result: &EndpointResult,
+) -> HashMap<String, String> {
+    extract
+        .iter()
+        .filter_map(
+            |(name, source)| match extract_value(source, endpoint_result) {
+                Ok(value) => Some((name.clone(), value)),
+                Err(reason) => {
+                    error!("Error: Could not extract value '{}': {}", name, reason);
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+// Compiles the regexes of a step's extracted values, so invalid ones fail when the
+// config is loaded
+pub fn compile_extract(
+    extract: &mut Option<HashMap<String, ExtractSource>>,
+    location: &str,
+) -> Result<(), InvalidExtractError> {
+    for (name, source) in extract.iter_mut().flatten() {
+        if let ExtractSource::Regex(regex) = source {
+            let compiled = Regex::new(&regex.pattern).map_err(|e| InvalidExtractError {
+                location: location.to_owned(),
+                name: name.clone(),
+                reason: format!("Invalid regex '{}': {}", regex.pattern, e),
+            })?;
+            regex.compiled = Some(compiled);
         }
     }
+    Ok(())
 }
 
-// TODO: We should be able to handle arrays
-fn get_nested_json_value(parts: &[&str], json_string: &String) -> String {
-    let json_value: Value = match serde_json::from_str(json_string) {
-        Ok(val) => val,
-        Err(_) => {
-            error!("Error parsing json response: {}", json_string);
-            return "".to_string();
+fn extract_value(
+    source: &ExtractSource,
+    endpoint_result: &EndpointResult,
+) -> Result<String, String> {
+    match source {
+        ExtractSource::JsonPath(path) => resolve_json_path(path, &endpoint_result.body),
+        ExtractSource::Regex(regex) => {
+            let pattern = &regex.pattern;
+            let uncompiled;
+            let regex = match &regex.compiled {
+                Some(compiled) => compiled,
+                None => {
+                    uncompiled = Regex::new(pattern)
+                        .map_err(|e| format!("Invalid regex '{}': {}", pattern, e))?;
+                    &uncompiled
+                }
+            };
+            let captures = regex
+                .captures(&endpoint_result.body)
+                .ok_or_else(|| format!("Regex '{}' did not match the response body.", pattern))?;
+            let value = captures.get(1).or_else(|| captures.get(0)).unwrap();
+            Ok(value.as_str().to_owned())
         }
-    };
+        ExtractSource::Header(name) => endpoint_result
+            .headers
+            .get(&name.to_lowercase())
+            .cloned()
+            .ok_or_else(|| format!("Header '{}' was not present in the response.", name)),
+    }
+}
 
-    let mut current_value = &json_value;
-    for part in parts {
-        current_value = match current_value.get(part) {
-            Some(value) => value,
-            None => {
-                error!("Error finding value in json payload: {}", part);
-                return "".to_string();
-            }
-        };
+// Returns `arg` for paths written as `name(arg)`
+fn function_argument<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+    path.strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+// Paths can index arrays, e.g. `items.0.id` or `items.-1.id`, and filter them,
+// e.g. `items[?(@.type=='primary')].id`. The first matching value is used.
+fn get_nested_json_value(path: &str, json_string: &str) -> Result<String, String> {
+    let json_value: Value = serde_json::from_str(json_string)
+        .map_err(|e| format!("Response body is not valid JSON for '{}': {}", path, e))?;
+    let json_path = JsonPath::parse(&to_json_path(path))
+        .map_err(|e| format!("Error parsing json path '{}': {}", path, e))?;
+
+    match json_path.query(&json_value).first() {
+        Some(value) => Ok(json_value_to_string(value)),
+        None => Err(format!("Error finding value in json payload: {}", path)),
+    }
+}
+
+// Converts a dotted variable path into a JSONPath expression
+fn to_json_path(path: &str) -> String {
+    let mut json_path = "$".to_owned();
+    for segment in split_outside_brackets(path, '.') {
+        let (name, selectors) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+        // Numbers can be array indexes or object keys, and only one of the two can match
+        if name.parse::<i64>().is_ok() {
+            json_path.push_str(&format!("[{},'{}']", name, name));
+        } else if !name.is_empty() {
+            let escaped = name.replace('\\', "\\\\").replace('\'', "\\'");
+            json_path.push_str(&format!("['{}']", escaped));
+        }
+        json_path.push_str(selectors);
     }
+    json_path
+}
 
-    json_value_to_string(current_value)
+// Splits on a separator, ignoring any inside brackets or quotes
+fn split_outside_brackets(text: &str, separator: char) -> Vec<&str> {
+    let mut segments = vec![];
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (index, character) in text.char_indices() {
+        match (quote, character) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(character),
+            (None, '[' | '(') => depth += 1,
+            (None, ']' | ')') => depth -= 1,
+            (None, c) if c == separator && depth == 0 => {
+                segments.push(&text[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    segments.push(&text[start..]);
+    segments
 }
 
 fn json_value_to_string(value: &Value) -> String {
@@ -150,11 +550,13 @@ async fn test_substitute_several_variables() {
             "get-token".to_string(),
             StepVariables {
                 response_body: body_str.to_string(),
+                ..Default::default()
             },
         )]),
+        vars: HashMap::new(),
     };
 
-    let result = substitute_variables(&content, &variables);
+    let result = substitute_variables(&content, &variables, &mut StepSubstitution::default());
     assert!(result.contains(r#""other_field": "value""#));
     assert!(result.contains(r#""token": "12345""#));
 }
@@ -171,8 +573,10 @@ async fn test_substitute_input_parameters() {
             "get-token".to_string(),
             StepVariables {
                 response_body: body_str.to_string(),
+                ..Default::default()
             },
         )]),
+        vars: HashMap::new(),
     };
 
     let input_parameters = Some(ProbeInputParameters {
@@ -181,19 +585,76 @@ async fn test_substitute_input_parameters() {
             "Authorization".to_owned(),
             "Bearer ${{steps.get-token.response.body.token}}".to_owned(),
         )])),
+        form: None,
+        multipart: None,
+        body_file: None,
+        body_file_contents: None,
+        query: None,
         timeout_seconds: None,
     });
 
-    let result = substitute_input_parameters(&input_parameters, &variables);
+    let result = substitute_input_parameters(
+        &input_parameters,
+        &variables,
+        &mut StepSubstitution::default(),
+    );
     assert_eq!(
         "Bearer 12345",
         result.unwrap().headers.unwrap()["Authorization"]
     );
 }
 
+#[tokio::test]
+async fn test_substitute_body_file() {
+    let text_path = std::env::temp_dir().join("prodzilla_body_file_substitution.json");
+    std::fs::write(&text_path, r#"{"order": "${{vars.order_id}}"}"#).unwrap();
+    let binary_path = std::env::temp_dir().join("prodzilla_body_file_substitution.bin");
+    let binary = [0xff, 0xfe, b'$', b'{', b'{', b'v', b'}', b'}'];
+    std::fs::write(&binary_path, binary).unwrap();
+
+    let mut variables = StoryVariables::new();
+    variables
+        .vars
+        .insert("order_id".to_owned(), "ord-7".to_owned());
+    let input_parameters = |path: &std::path::Path| {
+        Some(ProbeInputParameters {
+            headers: Some(HashMap::from([(
+                "X-Body".to_owned(),
+                "${{request.body}}".to_owned(),
+            )])),
+            body_file: Some(path.display().to_string()),
+            ..Default::default()
+        })
+    };
+
+    let result = substitute_input_parameters(
+        &input_parameters(&text_path),
+        &variables,
+        &mut StepSubstitution::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        br#"{"order": "ord-7"}"#.to_vec(),
+        result.body_file_contents.unwrap()
+    );
+    assert_eq!(r#"{"order": "ord-7"}"#, result.headers.unwrap()["X-Body"]);
+
+    let result = substitute_input_parameters(
+        &input_parameters(&binary_path),
+        &variables,
+        &mut StepSubstitution::default(),
+    )
+    .unwrap();
+    assert_eq!(binary.to_vec(), result.body_file_contents.unwrap());
+}
+
 #[tokio::test]
 async fn test_substitute_input_parameters_empty() {
-    let result = substitute_input_parameters(&None, &StoryVariables::new());
+    let result = substitute_input_parameters(
+        &None,
+        &StoryVariables::new(),
+        &mut StepSubstitution::default(),
+    );
     assert!(result.is_none());
 }
 
@@ -211,11 +672,13 @@ async fn test_substitute_variable_doesnt_exist_in_json() {
             "get-token".to_string(),
             StepVariables {
                 response_body: body_str.to_string(),
+                ..Default::default()
             },
         )]),
+        vars: HashMap::new(),
     };
 
-    let result = substitute_variables(&content, &variables);
+    let result = substitute_variables(&content, &variables, &mut StepSubstitution::default());
     assert_eq!("field: ".to_owned(), result);
 }
 
@@ -225,10 +688,357 @@ async fn test_substitute_variable_step_doesnt_exist() {
 
     let variables = StoryVariables {
         steps: HashMap::new(),
+        vars: HashMap::new(),
     };
 
-    let result = substitute_variables(&content, &variables);
+    let result = substitute_variables(&content, &variables, &mut StepSubstitution::default());
     assert_eq!("field: ".to_owned(), result);
 }
 
 // TODO test what happens with spaces in the ${{ steps.etc }}
+
+#[tokio::test]
+async fn test_substitute_xpath_and_css_values() {
+    let variables = StoryVariables {
+        steps: HashMap::from([
+            (
+                "soap".to_string(),
+                StepVariables {
+                    response_body: "<Envelope><Session token=\"abc.123\"/></Envelope>".to_owned(),
+                    ..Default::default()
+                },
+            ),
+            (
+                "namespaced".to_string(),
+                StepVariables {
+                    response_body:
+                        "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\">\
+                        <s:Session token=\"def.456\"/></s:Envelope>"
+                            .to_owned(),
+                    ..Default::default()
+                },
+            ),
+            (
+                "page".to_string(),
+                StepVariables {
+                    response_body: "<html><body><input class=\"csrf\" value=\"x\">\
+                        <span class=\"csrf-token\">tok</span></body></html>"
+                        .to_owned(),
+                    ..Default::default()
+                },
+            ),
+        ]),
+        vars: HashMap::new(),
+    };
+
+    let content = "${{steps.soap.response.body.xpath(/Envelope/Session/@token)}} ${{steps.page.response.body.css(span.csrf-token)}} ${{steps.namespaced.response.body.xpath(/s:Envelope/s:Session/@token)}}";
+    let result = substitute_variables(content, &variables, &mut StepSubstitution::default());
+
+    assert_eq!("abc.123 tok def.456", result);
+
+    // An undeclared prefix is reported as unresolved rather than panicking
+    let mut substitution = StepSubstitution::default();
+    let content = "${{steps.namespaced.response.body.xpath(/soap:Envelope)}}";
+    assert_eq!(
+        "",
+        substitute_variables(content, &variables, &mut substitution)
+    );
+    assert!(substitution.unresolved[0]
+        .reason
+        .contains("namespace prefix 'soap'"));
+}
+
+#[tokio::test]
+async fn test_substitute_array_values() {
+    let body_str = r#"{
+        "items": [
+            {"id": 1, "type": "secondary", "tags": ["a", "b"]},
+            {"id": 2, "type": "primary", "tags": ["c"]},
+            {"id": 3, "type": "secondary", "tags": []}
+        ],
+        "by_year": {"2023": 7, "2024": 12, "-1": 3}
+    }"#;
+    let variables = StoryVariables {
+        steps: HashMap::from([(
+            "list".to_string(),
+            StepVariables {
+                response_body: body_str.to_owned(),
+                ..Default::default()
+            },
+        )]),
+        vars: HashMap::new(),
+    };
+
+    let cases = [
+        ("${{steps.list.response.body.items.0.id}}", "1"),
+        ("${{steps.list.response.body.items.-1.id}}", "3"),
+        ("${{steps.list.response.body.items[1].tags.0}}", "c"),
+        (
+            "${{steps.list.response.body.items[?(@.type=='primary')].id}}",
+            "2",
+        ),
+        ("${{steps.list.response.body.items.0.tags}}", r#"["a","b"]"#),
+        ("${{steps.list.response.body.items.5.id}}", ""),
+        ("${{steps.list.response.body.by_year.2024}}", "12"),
+        ("${{steps.list.response.body.by_year.-1}}", "3"),
+    ];
+    for (content, expected) in cases {
+        assert_eq!(
+            expected,
+            substitute_variables(content, &variables, &mut StepSubstitution::default()),
+            "{}",
+            content
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_substitute_response_values() {
+    let variables = StoryVariables {
+        steps: HashMap::from([(
+            "login".to_string(),
+            StepVariables {
+                response_body: "{}".to_owned(),
+                response_headers: HashMap::from([(
+                    "location".to_owned(),
+                    "/sessions/1".to_owned(),
+                )]),
+                status_code: 302,
+                duration_ms: 85,
+            },
+        )]),
+        vars: HashMap::new(),
+    };
+
+    let cases = [
+        ("${{steps.login.response.headers.Location}}", "/sessions/1"),
+        ("${{steps.login.response.headers.set-cookie}}", ""),
+        ("${{steps.login.response.status_code}}", "302"),
+        ("${{steps.login.response.duration_ms}}", "85"),
+        ("${{steps.login.response.body}}", "{}"),
+        ("${{steps.login.request.body}}", ""),
+        ("${{steps.login.response.cookies}}", ""),
+        ("${{steps.login}}", ""),
+    ];
+    for (content, expected) in cases {
+        assert_eq!(
+            expected,
+            substitute_variables(content, &variables, &mut StepSubstitution::default()),
+            "{}",
+            content
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_substitute_records_unresolved_variables() {
+    let variables = StoryVariables::new();
+    let mut substitution = StepSubstitution::default();
+
+    let result = substitute_variables(
+        "Bearer ${{ steps.get-token.response.body.token }}${{generate.uuid}}${{unknown}}",
+        &variables,
+        &mut substitution,
+    );
+
+    assert_eq!("Bearer ", &result[..7]);
+    assert_eq!(2, substitution.unresolved.len());
+    assert_eq!(
+        "${{ steps.get-token.response.body.token }}",
+        substitution.unresolved[0].placeholder
+    );
+    assert!(substitution.unresolved[0].reason.contains("get-token"));
+    assert_eq!("${{unknown}}", substitution.unresolved[1].placeholder);
+}
+
+#[tokio::test]
+async fn test_extract_values() {
+    let endpoint_result = EndpointResult {
+        timestamp_request_started: chrono::Utc::now(),
+        timestamp_response_received: chrono::Utc::now(),
+        status_code: 200,
+        headers: HashMap::from([("location".to_owned(), "/orders/7".to_owned())]),
+        body: r#"{"data": {"session": {"token": "abc"}}, "note": "order-1234"}"#.to_owned(),
+        sensitive: false,
+        peer_ip: None,
+        http_version: "1.1".to_owned(),
+        trace_id: "".to_owned(),
+        span_id: "".to_owned(),
+    };
+    let extract = HashMap::from([
+        (
+            "token".to_owned(),
+            ExtractSource::JsonPath("$.data.session.token".to_owned()),
+        ),
+        (
+            "order".to_owned(),
+            ExtractSource::Regex(crate::probe::model::ExtractRegex {
+                pattern: r"order-(\d+)".to_owned(),
+                compiled: None,
+            }),
+        ),
+        (
+            "location".to_owned(),
+            ExtractSource::Header("Location".to_owned()),
+        ),
+        (
+            "missing".to_owned(),
+            ExtractSource::JsonPath("$.missing".to_owned()),
+        ),
+    ]);
+
+    let extracted = extract_values(&extract, &endpoint_result);
+
+    assert_eq!(3, extracted.len());
+    assert_eq!("abc", extracted["token"]);
+    assert_eq!("1234", extracted["order"]);
+    assert_eq!("/orders/7", extracted["location"]);
+
+    let variables = StoryVariables {
+        steps: HashMap::new(),
+        vars: extracted,
+    };
+    let mut substitution = StepSubstitution::default();
+    let result = substitute_variables(
+        "Bearer ${{vars.token}}${{vars.missing}}",
+        &variables,
+        &mut substitution,
+    );
+    assert_eq!("Bearer abc", result);
+    assert_eq!("${{vars.missing}}", substitution.unresolved[0].placeholder);
+}
+
+#[tokio::test]
+async fn test_generated_values_stable_within_step() {
+    let variables = StoryVariables::new();
+    let mut substitution = StepSubstitution::default();
+
+    let url = substitute_variables("/orders/${{generate.uuid}}", &variables, &mut substitution);
+    let body = substitute_variables(
+        r#"{"id": "${{ generate.uuid }}", "other": "${{generate.string(8)}}"}"#,
+        &variables,
+        &mut substitution,
+    );
+    let uuid = url.strip_prefix("/orders/").unwrap();
+    assert!(body.contains(&format!(r#""id": "{}""#, uuid)));
+
+    let next_url = substitute_variables(
+        "/orders/${{generate.uuid}}",
+        &variables,
+        &mut StepSubstitution::default(),
+    );
+    assert_ne!(url, next_url);
+}
+
+#[tokio::test]
+async fn test_substitute_functions() {
+    let variables = StoryVariables {
+        steps: HashMap::new(),
+        vars: HashMap::from([
+            ("user".to_owned(), "admin:secret".to_owned()),
+            ("key".to_owned(), "Jefe".to_owned()),
+            ("query".to_owned(), "a b&c".to_owned()),
+        ]),
+    };
+    let input_parameters = Some(ProbeInputParameters {
+        body: Some("what do ya want for nothing?".to_owned()),
+        headers: Some(HashMap::from([
+            (
+                "Authorization".to_owned(),
+                "Basic ${{ vars.user | base64 }}".to_owned(),
+            ),
+            (
+                "X-Signature".to_owned(),
+                "${{ hmac_sha256(vars.key, request.body) }}".to_owned(),
+            ),
+        ])),
+        form: None,
+        multipart: None,
+        body_file: None,
+        body_file_contents: None,
+        query: None,
+        timeout_seconds: None,
+    });
+
+    let mut substitution = StepSubstitution::default();
+    let result = substitute_input_parameters(&input_parameters, &variables, &mut substitution)
+        .unwrap()
+        .headers
+        .unwrap();
+
+    assert_eq!("Basic YWRtaW46c2VjcmV0", result["Authorization"]);
+    assert_eq!(
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        result["X-Signature"]
+    );
+
+    let cases = [
+        ("${{ vars.query | urlencode }}", "a%20b%26c"),
+        ("${{ vars.query | upper | urlencode }}", "A%20B%26C"),
+        ("${{ lower('ABC') }}", "abc"),
+        ("${{ sha256(vars.missing) }}", ""),
+        ("${{ vars.user | unknown }}", ""),
+    ];
+    for (content, expected) in cases {
+        let result = substitute_variables(content, &variables, &mut substitution);
+        assert_eq!(expected, result, "{}", content);
+    }
+    assert_eq!(2, substitution.unresolved.len());
+}
+
+#[tokio::test]
+async fn test_substitute_env_and_file_values() {
+    std::env::set_var("TEST_RUNTIME_SECRET", "Jefe");
+    let path = std::env::temp_dir().join("prodzilla_secret_token_test");
+    std::fs::write(&path, "token-value\n").unwrap();
+
+    let variables = StoryVariables::new();
+    let mut substitution = StepSubstitution::default();
+    let content = format!(
+        "${{{{ env.TEST_RUNTIME_SECRET }}}} ${{{{ file.{} }}}} ${{{{ hmac_sha256(env.TEST_RUNTIME_SECRET, 'what do ya want for nothing?') }}}} ${{{{ env.TEST_RUNTIME_MISSING }}}}",
+        path.display()
+    );
+    let result = substitute_variables(&content, &variables, &mut substitution);
+
+    assert_eq!(
+        "Jefe token-value 5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843 ",
+        result
+    );
+    assert_eq!(1, substitution.unresolved.len());
+}
+
+#[tokio::test]
+async fn test_static_variables_override_global() {
+    let global = HashMap::from([
+        (
+            "base_url".to_owned(),
+            "https://global.example.com".to_owned(),
+        ),
+        ("tenant".to_owned(), "global-tenant".to_owned()),
+    ]);
+    let local = Some(HashMap::from([(
+        "tenant".to_owned(),
+        "story-tenant".to_owned(),
+    )]));
+
+    let variables = StoryVariables::with_static_variables(&global, &local);
+    let result = substitute_variables(
+        "${{ vars.base_url }}/${{ vars.tenant }}",
+        &variables,
+        &mut StepSubstitution::default(),
+    );
+
+    assert_eq!("https://global.example.com/story-tenant", result);
+}
+
+#[tokio::test]
+async fn test_substitute_alerts() {
+    std::env::set_var("TEST_ALERT_WEBHOOK_TOKEN", "T0K3N");
+    let alerts = Some(vec![ProbeAlert {
+        url: "https://hooks.example.com/${{ env.TEST_ALERT_WEBHOOK_TOKEN }}".to_owned(),
+    }]);
+
+    let substituted = substitute_alerts(&alerts, &StoryVariables::new()).unwrap();
+
+    assert_eq!("https://hooks.example.com/T0K3N", substituted[0].url);
+}
@@ -5,8 +5,8 @@ pub mod probe_test_utils {
     use reqwest::StatusCode;
 
     use crate::probe::model::{
-        ExpectField, ExpectOperation, Probe, ProbeAlert, ProbeExpectation, ProbeInputParameters,
-        ProbeScheduleParameters,
+        ExpectField, ExpectOperation, Expectation, Probe, ProbeAlert, ProbeExpectation,
+        ProbeInputParameters, ProbeScheduleParameters,
     };
 
     pub fn probe_get_with_timeout_and_expected_status(
@@ -22,13 +22,21 @@ pub mod probe_test_utils {
             with: Some(ProbeInputParameters {
                 body: Some(body),
                 headers: Some(HashMap::new()),
+                form: None,
+                multipart: None,
+                body_file: None,
+                body_file_contents: None,
+                query: None,
                 timeout_seconds,
             }),
-            expectations: Some(vec![ProbeExpectation {
+            expectations: Some(vec![Expectation::Field(ProbeExpectation {
                 field: ExpectField::StatusCode,
                 operation: ExpectOperation::Equals,
                 value: status_code.as_str().into(),
-            }]),
+                schema: None,
+                ignore_paths: vec![],
+                compiled: Default::default(),
+            })]),
             schedule: ProbeScheduleParameters {
                 initial_delay: 0,
                 interval: 0,
@@ -36,6 +44,12 @@ pub mod probe_test_utils {
             alerts: None,
             tags: None,
             sensitive: false,
+            auth: None,
+            tls: None,
+            client: None,
+            resolve: None,
+            ip_family: None,
+            variables: None,
         }
     }
 
@@ -51,13 +65,21 @@ pub mod probe_test_utils {
             with: Some(ProbeInputParameters {
                 body: Some(body),
                 headers: Some(HashMap::new()),
+                form: None,
+                multipart: None,
+                body_file: None,
+                body_file_contents: None,
+                query: None,
                 timeout_seconds: None,
             }),
-            expectations: Some(vec![ProbeExpectation {
+            expectations: Some(vec![Expectation::Field(ProbeExpectation {
                 field: ExpectField::StatusCode,
                 operation: ExpectOperation::Equals,
                 value: status_code.as_str().into(),
-            }]),
+                schema: None,
+                ignore_paths: vec![],
+                compiled: Default::default(),
+            })]),
             schedule: ProbeScheduleParameters {
                 initial_delay: 0,
                 interval: 0,
@@ -65,6 +87,12 @@ pub mod probe_test_utils {
             alerts: None,
             tags: None,
             sensitive: false,
+            auth: None,
+            tls: None,
+            client: None,
+            resolve: None,
+            ip_family: None,
+            variables: None,
         }
     }
 
@@ -81,13 +109,21 @@ pub mod probe_test_utils {
             with: Some(ProbeInputParameters {
                 body: Some(body),
                 headers: Some(HashMap::new()),
+                form: None,
+                multipart: None,
+                body_file: None,
+                body_file_contents: None,
+                query: None,
                 timeout_seconds: None,
             }),
-            expectations: Some(vec![ProbeExpectation {
+            expectations: Some(vec![Expectation::Field(ProbeExpectation {
                 field: ExpectField::StatusCode,
                 operation: ExpectOperation::Equals,
                 value: status_code.as_str().into(),
-            }]),
+                schema: None,
+                ignore_paths: vec![],
+                compiled: Default::default(),
+            })]),
             schedule: ProbeScheduleParameters {
                 initial_delay: 0,
                 interval: 0,
@@ -95,6 +131,12 @@ pub mod probe_test_utils {
             alerts: Some(vec![ProbeAlert { url: alert_url }]),
             tags: None,
             sensitive: false,
+            auth: None,
+            tls: None,
+            client: None,
+            resolve: None,
+            ip_family: None,
+            variables: None,
         }
     }
 
@@ -110,19 +152,30 @@ pub mod probe_test_utils {
             with: Some(ProbeInputParameters {
                 body: Some(body),
                 headers: Some(HashMap::new()),
+                form: None,
+                multipart: None,
+                body_file: None,
+                body_file_contents: None,
+                query: None,
                 timeout_seconds: None,
             }),
             expectations: Some(vec![
-                ProbeExpectation {
+                Expectation::Field(ProbeExpectation {
                     field: ExpectField::StatusCode,
                     operation: ExpectOperation::Equals,
                     value: "200".to_owned(),
-                },
-                ProbeExpectation {
+                    schema: None,
+                    ignore_paths: vec![],
+                    compiled: Default::default(),
+                }),
+                Expectation::Field(ProbeExpectation {
                     field: ExpectField::Body,
                     operation: ExpectOperation::Equals,
                     value: expected_body,
-                },
+                    schema: None,
+                    ignore_paths: vec![],
+                    compiled: Default::default(),
+                }),
             ]),
             schedule: ProbeScheduleParameters {
                 initial_delay: 0,
@@ -131,6 +184,12 @@ pub mod probe_test_utils {
             alerts: None,
             tags: None,
             sensitive: false,
+            auth: None,
+            tls: None,
+            client: None,
+            resolve: None,
+            ip_family: None,
+            variables: None,
         }
     }
 }